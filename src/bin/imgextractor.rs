@@ -1,11 +1,16 @@
-use android_ext4::{DirectoryWalker, EntryAttributes, FileType, Volume, WalkItem};
+use android_ext4::{
+    EntryAttributes, File as Ext4File, FileType, ParallelWalker, Volume, WalkItem,
+    ext4::{FeaturePolicy, Node},
+};
 use clap::Parser;
 use indicatif::ProgressBar;
-use rayon::iter::{IntoParallelIterator, ParallelBridge, ParallelIterator};
-use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
-use std::sync::Arc;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[cfg(unix)]
@@ -37,9 +42,30 @@ struct Arguments {
     #[arg(short, long)]
     quiet: bool,
 
+    /// Refuse to extract images using features that would produce wrong or stale data
+    #[arg(long)]
+    strict: bool,
+
+    /// Number of metadata blocks to cache while walking and extracting (0 disables the cache)
+    #[arg(long, default_value_t = 8192)]
+    cache_blocks: usize,
+
     /// Number of threads to use for extraction (defaults to num_cpus / 4)
     #[arg(short = 't', long, default_value_t = num_cpus())]
     num_threads: usize,
+
+    /// Read file data in the order it is stored in the image instead of file by file
+    #[arg(long)]
+    physical_order: bool,
+
+    /// Size in MiB above which a file is read by several threads at once
+    #[arg(long, default_value_t = 256)]
+    parallel_threshold: u64,
+
+    /// Copy file data in the kernel (reflink or copy_file_range) instead of through userspace
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    kernel_copy: bool,
 }
 
 fn num_cpus() -> usize {
@@ -57,18 +83,29 @@ fn default_output_path() -> PathBuf {
 }
 
 /// Main extractor
-struct Extractor<F: Fn() -> BufReader<File> + Sync + Send> {
-    volume: Volume<BufReader<File>, F>,
+struct Extractor {
+    volume: Volume<File>,
     arguments: Arguments,
     mount_name: String,
     fsconfig: BufWriter<File>,
     contexts: BufWriter<File>,
+    /// Files copied with each `CopyMethod`, cheapest first
+    #[cfg(target_os = "linux")]
+    copy_methods: [AtomicU64; 3],
 }
 
-impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
-    fn new(reader_factory: F, arguments: Arguments) -> io::Result<Self> {
-        let volume = Volume::new(reader_factory)
+impl Extractor {
+    fn new(image: File, arguments: Arguments) -> io::Result<Self> {
+        let policy = if arguments.strict {
+            FeaturePolicy::Strict
+        } else {
+            FeaturePolicy::Permissive
+        };
+        let mut volume = Volume::open(image, policy)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}", e)))?;
+        if arguments.cache_blocks > 0 {
+            volume = volume.with_block_cache(arguments.cache_blocks);
+        }
         let mount_name = volume
             .name()
             .unwrap_or(
@@ -99,6 +136,8 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
             mount_name,
             fsconfig,
             contexts,
+            #[cfg(target_os = "linux")]
+            copy_methods: Default::default(),
         })
     }
 
@@ -106,6 +145,11 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
         self.arguments.output_dir.join(&self.mount_name)
     }
 
+    fn target_path(&self, path: &Path) -> PathBuf {
+        self.extract_dir()
+            .join(path.strip_prefix("/").unwrap_or(path))
+    }
+
     fn create_progress_bar(&self, len: u64, msg: &str) -> ProgressBar {
         if self.arguments.quiet {
             return ProgressBar::hidden();
@@ -133,17 +177,42 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
             eprintln!("Volume: {}", self.mount_name);
         }
 
+        for feature in self.volume.compatibility().unsupported() {
+            eprintln!("Warning: {}", feature);
+        }
+
+        match self.volume.mmp() {
+            Ok(Some(mmp)) => {
+                if let Some(warning) = mmp.warning() {
+                    eprintln!("Warning: {}", warning);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: failed to read MMP block: {}", e),
+        }
+
         let spinner = self.create_spinner("Scanning filesystem...");
 
-        // Collect all entries first
-        let items: Vec<WalkItem> = DirectoryWalker::from_path(&self.volume, "/")
+        // Collect all entries first, in a stable order so the config files are reproducible
+        let items: Vec<WalkItem> = ParallelWalker::from_path(&self.volume, "/")
             .map_err(|e| io::Error::other(format!("Walker error: {}", e)))?
-            .par_bridge()
+            .ordered(true)
             .filter_map(Result::ok)
             .collect();
 
         spinner.finish_with_message(format!("Found {} entries", items.len()));
 
+        // Regular files are only created here, their data is copied in one pass afterwards
+        let files: Vec<(u32, PathBuf)> = if self.arguments.physical_order {
+            items
+                .iter()
+                .filter(|item| matches!(item.r#type(), FileType::RegularFile))
+                .map(|item| (item.ino(), self.target_path(item.path())))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
         // Process entries
         let pb = self.create_progress_bar(items.len() as u64, "Extracting");
 
@@ -155,19 +224,25 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
             })
             .collect();
 
+        if !files.is_empty() {
+            self.copy_in_physical_order(&files)?;
+        }
+
         // Add root entries
         writeln!(self.fsconfig, "/ 0 0 0755")?;
         writeln!(self.fsconfig, "{} 0 0 0755", self.mount_name)?;
 
         for (path, attr) in attributes {
-            let fs_path = format!("{}{}", self.mount_name, path.display());
+            // Names are written byte for byte, they are not necessarily UTF-8
+            let mut fs_path = self.mount_name.as_bytes().to_vec();
+            fs_path.extend_from_slice(path.as_os_str().as_encoded_bytes());
             let escaped = escape_regex(&fs_path);
 
             // fs_config
+            self.fsconfig.write_all(&fs_path)?;
             writeln!(
                 self.fsconfig,
-                "{} {} {} {}",
-                fs_path,
+                " {} {} {}",
                 attr.uid(),
                 attr.gid(),
                 attr.mode_with_caps()
@@ -175,10 +250,14 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
 
             // SELinux contexts
             if let Some(selabel) = attr.selinux() {
-                writeln!(self.contexts, "/{} {}", escaped, selabel)?;
+                self.contexts.write_all(b"/")?;
+                self.contexts.write_all(&escaped)?;
+                writeln!(self.contexts, " {}", selabel)?;
                 // directory
                 if matches!(attr.mode().file_type(), Some(FileType::Directory)) {
-                    writeln!(self.contexts, "/{}(/.*)? {}", escaped, selabel)?;
+                    self.contexts.write_all(b"/")?;
+                    self.contexts.write_all(&escaped)?;
+                    writeln!(self.contexts, "(/.*)? {}", selabel)?;
                 }
             }
 
@@ -186,7 +265,8 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
             if matches!(attr.mode().file_type(), Some(FileType::RegularFile))
                 && path.to_string_lossy().contains("/system/build.prop")
             {
-                let dir_escaped = escape_regex(&self.mount_name);
+                let dir_escaped =
+                    String::from_utf8_lossy(&escape_regex(self.mount_name.as_bytes())).into_owned();
                 writeln!(self.contexts, "/{} u:object_r:rootfs:s0", dir_escaped)?;
                 writeln!(self.contexts, "/{}(/.*)? u:object_r:rootfs:s0", dir_escaped)?;
             }
@@ -197,6 +277,37 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
 
         pb.finish_with_message("Extraction complete");
 
+        if self.arguments.verbose
+            && let Some(stats) = self.volume.cache_stats()
+        {
+            eprintln!(
+                "Block cache: {} hits, {} misses ({:.1}% hit rate)",
+                stats.hits,
+                stats.misses,
+                stats.hit_rate() * 100.0
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.arguments.kernel_copy {
+            let [reflink, copy_file_range, buffered] = self
+                .copy_methods
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed));
+            if buffered > 0 {
+                eprintln!(
+                    "Warning: {} files fell back to a buffered copy, the kernel could not copy them",
+                    buffered
+                );
+            }
+            if self.arguments.verbose {
+                eprintln!(
+                    "Kernel copy: {} files reflinked, {} copied with copy_file_range, {} buffered",
+                    reflink, copy_file_range, buffered
+                );
+            }
+        }
+
         if !self.arguments.quiet {
             eprintln!("✓ Extraction completed successfully!");
             eprintln!("  Output: {}", self.arguments.output_dir.display());
@@ -208,8 +319,7 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
     fn process_item(&self, item: &WalkItem) -> io::Result<(PathBuf, EntryAttributes)> {
         let path = item.path();
 
-        let extract_dir = self.extract_dir();
-        let target = extract_dir.join(path.strip_prefix("/").unwrap_or(path));
+        let target = self.target_path(path);
 
         if let Some(parent) = target.parent()
             && !parent.exists()
@@ -217,29 +327,55 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
             fs::create_dir_all(parent)?;
         }
 
+        let open_error =
+            |e| io::Error::other(format!("Failed to open {}: {}", item.path().display(), e));
+
         match item.r#type() {
+            FileType::RegularFile if self.arguments.physical_order => {
+                File::create(&target)?.set_len(item.inode().size())?;
+            }
             FileType::RegularFile => {
                 let mut file = File::create(&target)?;
-                let mut file_reader = self.volume.open_file(item.path()).map_err(|e| {
-                    io::Error::other(format!(
-                        "Failed to open file {}: {}",
-                        item.path().display(),
-                        e
-                    ))
-                })?;
-                io::copy(&mut file_reader, &mut file)?;
+                let Node::File(mut file_reader) =
+                    self.volume.open_inode(item.ino()).map_err(open_error)?
+                else {
+                    return Err(io::Error::other(format!(
+                        "{} is no longer a regular file",
+                        item.path().display()
+                    )));
+                };
+                let copy_error = |e| {
+                    io::Error::other(format!("Failed to copy {}: {}", item.path().display(), e))
+                };
+
+                #[cfg(target_os = "linux")]
+                let kernel_copy = self.arguments.kernel_copy;
+                #[cfg(not(target_os = "linux"))]
+                let kernel_copy = false;
+
+                if kernel_copy {
+                    #[cfg(target_os = "linux")]
+                    {
+                        let method = file_reader.copy_to_file(&file).map_err(copy_error)?;
+                        self.copy_methods[method as usize].fetch_add(1, Ordering::Relaxed);
+                    }
+                } else if file_reader.size() >= self.arguments.parallel_threshold << 20 {
+                    file_reader
+                        .par_copy_to(&file, Ext4File::<File>::PARALLEL_CHUNK_SIZE)
+                        .map_err(copy_error)?;
+                } else {
+                    Self::copy_sparse(&mut file_reader, &mut file).map_err(copy_error)?;
+                }
             }
             FileType::SymbolicLink => {
-                let mut file_reader = self.volume.open_file(item.path()).map_err(|e| {
-                    io::Error::other(format!(
-                        "Failed to read symlink {}: {}",
-                        item.path().display(),
-                        e
-                    ))
-                })?;
-
-                let mut link_target = String::new();
-                file_reader.read_to_string(&mut link_target).map_err(|e| {
+                let Node::Symlink(link) = self.volume.open_inode(item.ino()).map_err(open_error)?
+                else {
+                    return Err(io::Error::other(format!(
+                        "{} is no longer a symlink",
+                        item.path().display()
+                    )));
+                };
+                let link_target = link.read_link().map_err(|e| {
                     io::Error::other(format!(
                         "Failed to read symlink target {}: {}",
                         item.path().display(),
@@ -257,17 +393,90 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
         Ok((item.path().to_owned(), item.attributes().clone()))
     }
 
+    /// Copy a file, seeking over its holes so the output stays sparse
+    fn copy_sparse(reader: &mut Ext4File<File>, output: &mut File) -> android_ext4::Result<()> {
+        let mut offset = 0;
+        while let Some(data) = reader.next_data(offset)? {
+            let hole = reader.next_hole(data)?.unwrap_or(reader.size());
+            reader.seek(SeekFrom::Start(data))?;
+            output.seek(SeekFrom::Start(data))?;
+            io::copy(&mut (&mut *reader).take(hole - data), output)?;
+            offset = hole;
+        }
+        output.set_len(reader.size())?;
+        Ok(())
+    }
+
+    /// Copy the data of regular files in a single pass over the image, in physical block order
+    fn copy_in_physical_order(&self, files: &[(u32, PathBuf)]) -> io::Result<()> {
+        // Files are reopened as their data comes up, keep a bounded number open
+        const MAX_OPEN_FILES: usize = 256;
+
+        let plan = self
+            .volume
+            .plan_extraction(files.iter().map(|(ino, _)| *ino))
+            .map_err(|e| io::Error::other(format!("Failed to plan extraction: {}", e)))?;
+        let pb = self.create_progress_bar(plan.total_bytes(), "Copying file data");
+
+        // Open outputs with the run that last used them, the least recent is closed first
+        let mut open_files: HashMap<usize, (File, u64)> = HashMap::new();
+        let mut failed: Vec<Option<io::Error>> = (0..files.len()).map(|_| None).collect();
+        let mut run = 0;
+        plan.execute(&self.volume, |index, offset, data| {
+            pb.inc(data.len() as u64);
+            run += 1;
+            if failed[index].is_some() {
+                return Ok(());
+            }
+
+            if !open_files.contains_key(&index) {
+                if open_files.len() >= MAX_OPEN_FILES
+                    && let Some(oldest) = open_files
+                        .iter()
+                        .min_by_key(|(_, (_, last_used))| *last_used)
+                        .map(|(oldest, _)| *oldest)
+                {
+                    open_files.remove(&oldest);
+                }
+                match OpenOptions::new().write(true).open(&files[index].1) {
+                    Ok(file) => open_files.insert(index, (file, run)),
+                    Err(e) => {
+                        failed[index] = Some(e);
+                        return Ok(());
+                    }
+                };
+            }
+
+            let (file, last_used) = open_files
+                .get_mut(&index)
+                .expect("output file was just opened");
+            *last_used = run;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(data)?;
+            Ok(())
+        })
+        .map_err(|e| io::Error::other(format!("Failed to copy file data: {}", e)))?;
+
+        pb.finish_and_clear();
+        for ((_, path), error) in files.iter().zip(failed) {
+            if let Some(e) = error {
+                eprintln!("Warning: failed to open {}: {}", path.display(), e);
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(unix)]
-    fn create_symlink(link: &str, target: &PathBuf) {
+    fn create_symlink(link: &Path, target: &PathBuf) {
         let _ = symlink(link, target);
     }
 
     #[cfg(windows)]
-    fn create_symlink(link: &str, target: &PathBuf) {
+    fn create_symlink(link: &Path, target: &PathBuf) {
         if let Ok(mut file) = File::create(target) {
             let marker = b"!<symlink>\xff\xfe";
             let _ = file.write_all(marker);
-            for c in link.encode_utf16() {
+            for c in link.to_string_lossy().encode_utf16() {
                 let _ = file.write_all(&c.to_le_bytes());
             }
             let _ = file.write_all(&[0, 0]);
@@ -276,14 +485,12 @@ impl<F: Fn() -> BufReader<File> + Sync + Send> Extractor<F> {
 }
 
 /// Escape special regex characters for file_contexts
-fn escape_regex(s: &str) -> String {
-    const SPECIAL: &[char] = &[
-        '\\', '^', '$', '.', '|', '?', '*', '+', '(', ')', '{', '}', '[', ']',
-    ];
-    let mut result = String::with_capacity(s.len() * 2);
-    for c in s.chars() {
+fn escape_regex(s: &[u8]) -> Vec<u8> {
+    const SPECIAL: &[u8] = b"\\^$.|?*+(){}[]";
+    let mut result = Vec::with_capacity(s.len() * 2);
+    for &c in s {
         if SPECIAL.contains(&c) {
-            result.push('\\');
+            result.push(b'\\');
         }
         result.push(c);
     }
@@ -309,17 +516,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("Using {} threads for extraction", args.num_threads);
     }
 
-    // Create an Arc-wrapped path for the reader factory
-    let image_path = Arc::new(args.image.clone());
-
-    Extractor::new(
-        move || {
-            let file = File::open(image_path.as_ref()).expect("Failed to open image file");
-            BufReader::new(file)
-        },
-        args,
-    )?
-    .run()?;
+    let image = File::open(&args.image)?;
+    Extractor::new(image, args)?.run()?;
 
     Ok(())
 }
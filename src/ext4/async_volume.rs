@@ -0,0 +1,770 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
+
+use crate::{
+    Error, Result, WalkItem,
+    ext4::{
+        ADDR_SIZE, DirectoryEntry,
+        block::{BlockGroupDescriptor, GroupTable},
+        compat::{CompatibilityReport, FeaturePolicy},
+        device::BlockDevice,
+        directory::{CursorStep, DirectoryCursor},
+        extent::{ExtentMap, ExtentTreeWalk},
+        inode::Inode,
+        metadata::Metadata,
+        orphan::{Orphan, OrphanPolicy, OrphanScan, deleted_orphans},
+        resolve::{MAX_SYMLINK_HOPS, PathResolver, SymlinkPolicy},
+        superblock::Superblock,
+        xattr::{self, XAttrEntry},
+    },
+    utils::os_str_from_bytes,
+};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Async counterpart of `BlockDevice`
+pub trait AsyncBlockDevice: Send + Sync {
+    /// Fill `buf` with the bytes starting at `offset`
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Get the size of the device in bytes
+    fn size(&self) -> impl Future<Output = Result<u64>> + Send;
+}
+
+impl AsyncBlockDevice for Vec<u8> {
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        BlockDevice::read_at(self, offset, buf)
+    }
+
+    async fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl<T: AsyncBlockDevice + ?Sized> AsyncBlockDevice for Arc<T> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> impl Future<Output = Result<()>> + Send {
+        (**self).read_at(offset, buf)
+    }
+
+    fn size(&self) -> impl Future<Output = Result<u64>> + Send {
+        (**self).size()
+    }
+}
+
+/// A single seekable async reader, such as `tokio::fs::File`, shared behind a lock
+impl<R: AsyncRead + AsyncSeek + Unpin + Send> AsyncBlockDevice for tokio::sync::Mutex<R> {
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut reader = self.lock().await;
+        reader.seek(SeekFrom::Start(offset)).await?;
+        reader.read_exact(buf).await?;
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<u64> {
+        Ok(self.lock().await.seek(SeekFrom::End(0)).await?)
+    }
+}
+
+/// Adapter running a synchronous `BlockDevice` on tokio's blocking thread pool
+#[derive(Debug)]
+pub struct Blocking<D>(Arc<D>);
+
+impl<D: BlockDevice + Send + Sync + 'static> Blocking<D> {
+    pub fn new(device: D) -> Self {
+        Self(Arc::new(device))
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&D) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let device = Arc::clone(&self.0);
+        tokio::task::spawn_blocking(move || f(&device))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+impl<D: BlockDevice + Send + Sync + 'static> AsyncBlockDevice for Blocking<D> {
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let length = buf.len();
+        let data = self
+            .run(move |device| {
+                let mut data = vec![0u8; length];
+                device.read_at(offset, &mut data).map(|_| data)
+            })
+            .await?;
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<u64> {
+        self.run(|device| device.size()).await
+    }
+}
+
+/// Low-level async reader for inode data
+///
+/// Only performs the reads, mapping offsets to blocks is shared with `InodeReader`.
+struct AsyncInodeReader<D: AsyncBlockDevice> {
+    device: Arc<D>,
+    block_size: u32,
+}
+
+impl<D: AsyncBlockDevice> Clone for AsyncInodeReader<D> {
+    fn clone(&self) -> Self {
+        Self {
+            device: Arc::clone(&self.device),
+            block_size: self.block_size,
+        }
+    }
+}
+
+impl<D: AsyncBlockDevice> AsyncInodeReader<D> {
+    async fn read_block(&self, block_num: u64) -> Result<Vec<u8>> {
+        let mut buffer = vec![0u8; self.block_size as usize];
+        self.device
+            .read_at(block_num * self.block_size as u64, &mut buffer)
+            .await?;
+        Ok(buffer)
+    }
+
+    async fn read_all(&self, inode: &Inode) -> Result<Vec<u8>> {
+        let extents = self.extent_map(inode).await?;
+        let mut data = vec![0u8; inode.size() as usize];
+        self.read_into(inode, &extents, 0, &mut data).await?;
+        Ok(data)
+    }
+
+    /// Build the extent map of an inode, empty if it does not use extents
+    async fn extent_map(&self, inode: &Inode) -> Result<ExtentMap> {
+        if !inode.uses_extents() || inode.is_fast_symlink() {
+            return Ok(ExtentMap::default());
+        }
+
+        let mut walk = ExtentTreeWalk::new(&inode.block, self.block_size)?;
+        while let Some(block_num) = walk.next_block() {
+            walk.load(&self.read_block(block_num).await?)?;
+        }
+        Ok(walk.finish())
+    }
+
+    /// Read data at a given offset into `buf`, returning the number of bytes read
+    ///
+    /// `extents` must be the map built by `extent_map` for the same inode.
+    async fn read_into(
+        &self,
+        inode: &Inode,
+        extents: &ExtentMap,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let file_size = inode.size();
+        if offset >= file_size {
+            return Ok(0);
+        }
+
+        let length = std::cmp::min(buf.len() as u64, file_size - offset) as usize;
+        let buf = &mut buf[..length];
+
+        if inode.is_fast_symlink() {
+            inode.read_inline(offset, buf);
+        } else if inode.uses_extents() {
+            // Holes and unwritten extents read as zeros
+            buf.fill(0);
+            for (dest, physical_offset) in extents.segments(offset, length, self.block_size) {
+                self.device.read_at(physical_offset, &mut buf[dest]).await?;
+            }
+        } else {
+            self.read_via_indirect(inode, offset, buf).await?;
+        }
+
+        Ok(length)
+    }
+
+    async fn read_via_indirect(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let block_size = self.block_size as u64;
+        let start_block = offset / block_size;
+        let end_block = (offset + buf.len() as u64).div_ceil(block_size);
+        let mut bytes_read = 0;
+
+        for block_idx in start_block..end_block {
+            let block_offset = if block_idx == start_block {
+                (offset % block_size) as usize
+            } else {
+                0
+            };
+            let to_read = std::cmp::min(
+                self.block_size as usize - block_offset,
+                buf.len() - bytes_read,
+            );
+            let dest = &mut buf[bytes_read..bytes_read + to_read];
+
+            let physical_block = self.resolve_block(inode, block_idx as u32).await?;
+            if physical_block == 0 {
+                dest.fill(0);
+            } else {
+                let physical_offset = physical_block * block_size + block_offset as u64;
+                self.device.read_at(physical_offset, dest).await?;
+            }
+
+            bytes_read += to_read;
+        }
+
+        Ok(())
+    }
+
+    async fn resolve_block(&self, inode: &Inode, logical_block: u32) -> Result<u64> {
+        let path = inode.block_map_path(logical_block, self.block_size);
+        let mut block_num = path.block();
+        for &index in path.indices() {
+            if block_num == 0 {
+                break;
+            }
+            let mut addr = [0u8; ADDR_SIZE as usize];
+            let offset = block_num * self.block_size as u64 + (index * ADDR_SIZE) as u64;
+            self.device.read_at(offset, &mut addr).await?;
+            block_num = u32::from_le_bytes(addr) as u64;
+        }
+        Ok(block_num)
+    }
+
+    async fn read_xattrs(&self, inode: &Inode) -> Result<Vec<XAttrEntry>> {
+        let mut xattrs = inode.xattrs().to_vec();
+
+        if let Some(xattr_block) = inode.xattr_block_number() {
+            let block_data = self.read_block(xattr_block).await?;
+            if let Ok(block_xattrs) = xattr::parse_xattrs_from_block(&block_data) {
+                xattrs.extend(block_xattrs);
+            }
+        }
+
+        Ok(xattrs)
+    }
+
+    /// Find an entry by its raw name bytes in a directory
+    async fn find(&self, directory: &Inode, name: &[u8]) -> Result<Option<DirectoryEntry>> {
+        let extents = self.extent_map(directory).await?;
+        let mut cursor = DirectoryCursor::new(directory.size(), self.block_size);
+        loop {
+            match cursor.step() {
+                CursorStep::Entry(entry) if entry.name_bytes() == name => return Ok(Some(entry)),
+                CursorStep::Entry(_) => {}
+                CursorStep::End => return Ok(None),
+                CursorStep::NeedBlock(offset) => {
+                    let length = self
+                        .read_into(directory, &extents, offset, cursor.block_buffer())
+                        .await?;
+                    cursor.block_loaded(length);
+                }
+            }
+        }
+    }
+}
+
+/// An ext4 filesystem volume read through an `AsyncBlockDevice`
+pub struct AsyncVolume<D: AsyncBlockDevice> {
+    reader: AsyncInodeReader<D>,
+    superblock: Arc<Superblock>,
+    compatibility: Arc<CompatibilityReport>,
+    groups: GroupTable,
+    deleted_orphans: Arc<HashSet<u32>>,
+}
+
+impl<D: AsyncBlockDevice> Clone for AsyncVolume<D> {
+    fn clone(&self) -> Self {
+        Self {
+            reader: self.reader.clone(),
+            superblock: Arc::clone(&self.superblock),
+            compatibility: Arc::clone(&self.compatibility),
+            groups: self.groups.clone(),
+            deleted_orphans: Arc::clone(&self.deleted_orphans),
+        }
+    }
+}
+
+impl<D: AsyncBlockDevice> AsyncVolume<D> {
+    /// Maximum number of symlinks followed while resolving a single path
+    pub const MAX_SYMLINK_HOPS: u32 = MAX_SYMLINK_HOPS;
+
+    /// Create a new volume from an async block device
+    pub async fn new(device: D) -> Result<Self> {
+        Self::open(device, FeaturePolicy::Permissive).await
+    }
+
+    /// Create a new volume from an async block device, checking features against a policy
+    pub async fn open(device: D, policy: FeaturePolicy) -> Result<Self> {
+        let mut sb_buf = vec![0u8; Superblock::SIZE];
+        device
+            .read_at(Superblock::SUPERBLOCK_OFFSET, &mut sb_buf)
+            .await?;
+        let superblock = Superblock::parse(&sb_buf)?;
+
+        let compatibility = CompatibilityReport::new(&superblock);
+        if policy == FeaturePolicy::Strict && !compatibility.is_safe() {
+            return Err(Error::UnsupportedFeatures(compatibility));
+        }
+
+        let (offset, length) = GroupTable::location(&superblock)?;
+        let mut table = vec![0u8; length];
+        device.read_at(offset, &mut table).await?;
+        let groups = GroupTable::parse(&superblock, &table)?;
+
+        Ok(Self {
+            reader: AsyncInodeReader {
+                device: Arc::new(device),
+                block_size: superblock.block_size(),
+            },
+            superblock: Arc::new(superblock),
+            compatibility: Arc::new(compatibility),
+            groups,
+            deleted_orphans: Arc::new(HashSet::new()),
+        })
+    }
+
+    /// Get the underlying block device
+    pub fn device(&self) -> &D {
+        &self.reader.device
+    }
+
+    /// Get the volume name
+    pub fn name(&self) -> Option<&str> {
+        Some(self.superblock.volume_name()).filter(|name| !name.is_empty())
+    }
+
+    /// Get the superblock
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    /// Get the features of this volume the reader does not fully support
+    pub fn compatibility(&self) -> &CompatibilityReport {
+        &self.compatibility
+    }
+
+    /// Get the block size
+    pub fn block_size(&self) -> u32 {
+        self.reader.block_size
+    }
+
+    /// Get a block group descriptor from the table loaded when the volume was opened
+    pub fn read_block_group_descriptor(&self, bg_index: u32) -> Result<BlockGroupDescriptor> {
+        self.groups.get(bg_index)
+    }
+
+    /// Iterate over the descriptors of all block groups, in group order
+    pub fn block_groups(&self) -> std::slice::Iter<'_, BlockGroupDescriptor> {
+        self.groups.iter()
+    }
+
+    /// Read an inode from the filesystem
+    pub async fn read_inode(&self, inode_num: u32) -> Result<Inode> {
+        let offset = self.groups.inode_offset(&self.superblock, inode_num)?;
+        let mut buffer = vec![0u8; self.superblock.inode_size() as usize];
+        self.reader.device.read_at(offset, &mut buffer).await?;
+        Inode::parse(&buffer)
+    }
+
+    /// Enumerate every orphaned inode on the volume
+    pub async fn orphans(&self) -> Result<Vec<Orphan>> {
+        let mut scan = OrphanScan::new(&self.superblock);
+
+        if let Some(orphan_file) = self.superblock.orphan_file_inode() {
+            let inode = self.read_inode(orphan_file).await?;
+            if inode.size() != 0 {
+                let data = self.reader.read_all(&inode).await?;
+                scan.load_orphan_file(&data, self.block_size())?;
+            }
+        }
+
+        while let Some(inode_number) = scan.next_inode()? {
+            scan.found(inode_number, self.read_inode(inode_number).await?);
+        }
+
+        Ok(scan.finish())
+    }
+
+    /// Choose how orphaned inodes are presented by this volume
+    pub async fn with_orphan_policy(mut self, policy: OrphanPolicy) -> Result<Self> {
+        let deleted = match policy {
+            OrphanPolicy::AsStored => HashSet::new(),
+            OrphanPolicy::Process => deleted_orphans(&self.orphans().await?),
+        };
+        self.deleted_orphans = Arc::new(deleted);
+        Ok(self)
+    }
+
+    fn is_deleted_orphan(&self, inode_number: u32) -> bool {
+        self.deleted_orphans.contains(&inode_number)
+    }
+
+    /// Resolve a path to its inode and the path it resolved to, like `Volume::resolve_path`
+    async fn resolve_path(&self, path: &Path, policy: SymlinkPolicy) -> Result<(Inode, PathBuf)> {
+        let root = self.read_inode(Inode::ROOT_INODE).await?;
+        let mut resolver = PathResolver::new(path, policy, root);
+
+        while let Some(lookup) = resolver.next_lookup()? {
+            let child = match self.reader.find(lookup.parent, lookup.name).await? {
+                Some(entry) if !self.is_deleted_orphan(entry.inode) => {
+                    Some((entry.inode, self.read_inode(entry.inode).await?))
+                }
+                _ => None,
+            };
+
+            if let Some(link) = resolver.found(child)? {
+                let target = self.symlink_target(link).await?;
+                resolver.follow(&target)?;
+            }
+        }
+
+        Ok(resolver.finish())
+    }
+
+    async fn symlink_target(&self, inode: &Inode) -> Result<PathBuf> {
+        let target = self.reader.read_all(inode).await?;
+        Ok(PathBuf::from(os_str_from_bytes(&target).into_owned()))
+    }
+
+    /// Lookup a path and return its inode, following symlinks in every component but the last
+    pub async fn lookup_path(&self, path: impl AsRef<Path>) -> Result<Inode> {
+        self.lookup_path_with(path, SymlinkPolicy::FollowIntermediate)
+            .await
+    }
+
+    /// Lookup a path with the given symlink policy and return its inode
+    pub async fn lookup_path_with(
+        &self,
+        path: impl AsRef<Path>,
+        policy: SymlinkPolicy,
+    ) -> Result<Inode> {
+        self.resolve_path(path.as_ref(), policy)
+            .await
+            .map(|(inode, _)| inode)
+    }
+
+    /// Read the target of a symlink without following it
+    pub async fn read_link(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let (inode, _) = self
+            .resolve_path(path, SymlinkPolicy::FollowIntermediate)
+            .await?;
+        if !inode.is_symlink() {
+            return Err(Error::NotASymlink(format!("{}", path.display())));
+        }
+        self.symlink_target(&inode).await
+    }
+
+    /// Open a file for reading, following symlinks in every component but the last
+    pub async fn open_file(&self, path: impl AsRef<Path>) -> Result<AsyncFile<D>> {
+        let (inode, path) = self
+            .resolve_path(path.as_ref(), SymlinkPolicy::FollowIntermediate)
+            .await?;
+        if !inode.is_regular_file() && !inode.is_symlink() {
+            return Err(Error::NotAFile(format!("{}", path.display())));
+        }
+
+        Ok(AsyncFile {
+            reader: self.reader.clone(),
+            metadata: Metadata::new(&inode, &self.superblock),
+            extents: Arc::new(self.reader.extent_map(&inode).await?),
+            inode: Arc::new(inode),
+            position: 0,
+            path,
+            pending: None,
+            leftover: Vec::new(),
+        })
+    }
+
+    /// Walk a directory tree recursively, following symlinks to reach the starting directory
+    pub async fn walk(&self, path: impl AsRef<Path>) -> Result<AsyncDirectoryWalker<D>> {
+        let (inode, path) = self
+            .resolve_path(path.as_ref(), SymlinkPolicy::FollowAll)
+            .await?;
+        if !inode.is_directory() {
+            return Err(Error::NotADirectory(format!("{}", path.display())));
+        }
+
+        let directory = WalkDirectory::new(&self.reader, path, inode).await?;
+        Ok(AsyncDirectoryWalker {
+            state: Some(WalkState {
+                volume: self.clone(),
+                stack: vec![directory],
+            }),
+            pending: None,
+        })
+    }
+}
+
+/// A file read through an `AsyncVolume`
+pub struct AsyncFile<D: AsyncBlockDevice> {
+    reader: AsyncInodeReader<D>,
+    inode: Arc<Inode>,
+    extents: Arc<ExtentMap>,
+    metadata: Metadata,
+    position: u64,
+    path: PathBuf,
+    pending: Option<BoxFuture<Result<Vec<u8>>>>,
+    /// Bytes read at `position` that did not fit in the caller's buffer
+    leftover: Vec<u8>,
+}
+
+impl<D: AsyncBlockDevice> AsyncFile<D> {
+    /// Get the file size
+    pub fn size(&self) -> u64 {
+        self.inode.size()
+    }
+
+    /// Get the current position in the file
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get a reference to the inode
+    pub fn inode(&self) -> &Inode {
+        &self.inode
+    }
+
+    /// Get the metadata of this file
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Check if this file is a symlink
+    pub fn is_symlink(&self) -> bool {
+        self.inode.is_symlink()
+    }
+
+    /// Get the path of this file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read bytes starting at `offset` without moving the file position
+    pub async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.reader
+            .read_into(&self.inode, &self.extents, offset, buf)
+            .await
+    }
+
+    /// Read all contents of the file
+    pub async fn read_all(&self) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; self.size() as usize];
+        self.read_at(&mut data, 0).await?;
+        Ok(data)
+    }
+
+    /// Move as many leftover bytes as fit into `buf`, advancing the position
+    fn take_leftover(&mut self, buf: &mut ReadBuf<'_>) {
+        let length = std::cmp::min(self.leftover.len(), buf.remaining());
+        buf.put_slice(&self.leftover[..length]);
+        self.leftover.drain(..length);
+        self.position += length as u64;
+    }
+}
+
+impl<D: AsyncBlockDevice + 'static> AsyncRead for AsyncFile<D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.leftover.is_empty() {
+            this.take_leftover(buf);
+            return Poll::Ready(Ok(()));
+        }
+
+        let pending = match &mut this.pending {
+            Some(pending) => pending,
+            None => {
+                if this.position >= this.size() || buf.remaining() == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+
+                let length = std::cmp::min(buf.remaining() as u64, this.size() - this.position);
+                let reader = this.reader.clone();
+                let inode = Arc::clone(&this.inode);
+                let extents = Arc::clone(&this.extents);
+                let offset = this.position;
+                this.pending.insert(Box::pin(async move {
+                    let mut data = vec![0u8; length as usize];
+                    let read = reader
+                        .read_into(&inode, &extents, offset, &mut data)
+                        .await?;
+                    data.truncate(read);
+                    Ok(data)
+                }))
+            }
+        };
+
+        let result = ready!(pending.as_mut().poll(cx));
+        this.pending = None;
+
+        // The buffer passed to this poll may be smaller than the one the
+        // read was sized for
+        this.leftover = result.map_err(io::Error::other)?;
+        this.take_leftover(buf);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<D: AsyncBlockDevice> AsyncSeek for AsyncFile<D> {
+    fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => this.size() as i64 + offset,
+            SeekFrom::Current(offset) => this.position as i64 + offset,
+        };
+
+        if new_pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to negative position",
+            ));
+        }
+
+        // A read still in flight was for the old position
+        this.pending = None;
+        this.leftover.clear();
+        this.position = new_pos as u64;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+/// The walk state handed back by a step together with the item it produced
+type WalkStep<D> = (WalkState<D>, Option<Result<WalkItem>>);
+
+/// A directory being listed by a walk
+struct WalkDirectory {
+    path: PathBuf,
+    inode: Inode,
+    extents: ExtentMap,
+    cursor: DirectoryCursor,
+}
+
+impl WalkDirectory {
+    async fn new<D: AsyncBlockDevice>(
+        reader: &AsyncInodeReader<D>,
+        path: PathBuf,
+        inode: Inode,
+    ) -> Result<Self> {
+        Ok(Self {
+            extents: reader.extent_map(&inode).await?,
+            cursor: DirectoryCursor::new(inode.size(), reader.block_size),
+            path,
+            inode,
+        })
+    }
+}
+
+/// State of a walk, moved in and out of the future that advances it
+struct WalkState<D: AsyncBlockDevice> {
+    volume: AsyncVolume<D>,
+    stack: Vec<WalkDirectory>,
+}
+
+impl<D: AsyncBlockDevice> WalkState<D> {
+    async fn next(&mut self) -> Option<Result<WalkItem>> {
+        let reader = &self.volume.reader;
+
+        while let Some(directory) = self.stack.last_mut() {
+            let entry = match directory.cursor.step() {
+                CursorStep::Entry(entry) => entry,
+                CursorStep::End => {
+                    self.stack.pop();
+                    continue;
+                }
+                CursorStep::NeedBlock(offset) => {
+                    let buffer = directory.cursor.block_buffer();
+                    match reader
+                        .read_into(&directory.inode, &directory.extents, offset, buffer)
+                        .await
+                    {
+                        Ok(length) => directory.cursor.block_loaded(length),
+                        Err(e) => {
+                            directory.cursor.finish();
+                            return Some(Err(e));
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            if entry.is_dot_or_dotdot() || self.volume.is_deleted_orphan(entry.inode) {
+                continue;
+            }
+
+            let item_path = directory.path.join(entry.name());
+            let inode = match self.volume.read_inode(entry.inode).await {
+                Ok(inode) => inode,
+                Err(e) => return Some(Err(e)),
+            };
+            let xattrs = reader.read_xattrs(&inode).await.unwrap_or_default();
+
+            if inode.is_directory() {
+                match WalkDirectory::new(reader, item_path.clone(), inode.clone()).await {
+                    Ok(directory) => self.stack.push(directory),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            return Some(Ok(WalkItem::new(
+                item_path,
+                entry,
+                inode,
+                &self.volume.superblock,
+                &xattrs,
+            )));
+        }
+
+        None
+    }
+}
+
+/// An async stream for recursive directory traversal
+pub struct AsyncDirectoryWalker<D: AsyncBlockDevice> {
+    state: Option<WalkState<D>>,
+    pending: Option<BoxFuture<WalkStep<D>>>,
+}
+
+impl<D: AsyncBlockDevice + 'static> Stream for AsyncDirectoryWalker<D> {
+    type Item = Result<WalkItem>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        let pending = match &mut this.pending {
+            Some(pending) => pending,
+            None => {
+                let Some(mut state) = this.state.take() else {
+                    return Poll::Ready(None);
+                };
+                this.pending.insert(Box::pin(async move {
+                    let item = state.next().await;
+                    (state, item)
+                }))
+            }
+        };
+
+        let (state, item) = ready!(pending.as_mut().poll(cx));
+        this.pending = None;
+        if item.is_some() {
+            this.state = Some(state);
+        }
+        Poll::Ready(item)
+    }
+}
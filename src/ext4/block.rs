@@ -1,10 +1,17 @@
 use bitflags::bitflags;
-use nom::Finish;
-use nom_derive::{NomLE, Parse};
+use nom::{
+    Finish, IResult,
+    combinator::cond,
+    number::complete::{le_u16, le_u32},
+};
 
+#[cfg(feature = "std")]
+use crate::ext4::superblock::Superblock;
 use crate::{Error, ParseContext, Result};
+#[cfg(feature = "std")]
+use std::{slice, sync::Arc};
 
-#[derive(Debug, Clone, Copy, NomLE)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
 pub struct BlockGroupDescriptor {
     block_bitmap_lo: u32,
@@ -13,7 +20,6 @@ pub struct BlockGroupDescriptor {
     free_blocks_count_lo: u16,
     free_inodes_count_lo: u16,
     used_dirs_count_lo: u16,
-    #[nom(Parse = "Flags::parse")]
     flags: Flags,
     exclude_bitmap_lo: u32,
     block_bitmap_csum_lo: u16,
@@ -21,27 +27,17 @@ pub struct BlockGroupDescriptor {
     itable_unused_lo: u16,
     checksum: u16,
 
-    #[nom(Cond = "i.len() >= 32")]
+    // Only present in 64-byte descriptors
     block_bitmap_hi: Option<u32>,
-    #[nom(Cond = "i.len() >= 32")]
     inode_bitmap_hi: Option<u32>,
-    #[nom(Cond = "i.len() >= 32")]
     inode_table_first_block_hi: Option<u32>,
-    #[nom(Cond = "i.len() >= 32")]
     free_blocks_count_hi: Option<u16>,
-    #[nom(Cond = "i.len() >= 32")]
     free_inodes_count_hi: Option<u16>,
-    #[nom(Cond = "i.len() >= 32")]
     used_dirs_count_hi: Option<u16>,
-    #[nom(Cond = "i.len() >= 32")]
     itable_unused_hi: Option<u16>,
-    #[nom(Cond = "i.len() >= 32")]
     exclude_bitmap_hi: Option<u32>,
-    #[nom(Cond = "i.len() >= 32")]
     block_bitmap_csum_hi: Option<u16>,
-    #[nom(Cond = "i.len() >= 32")]
     inode_bitmap_csum_hi: Option<u16>,
-    #[nom(Cond = "i.len() >= 32")]
     reserved: Option<u32>,
 }
 
@@ -50,12 +46,74 @@ impl BlockGroupDescriptor {
     pub const MAX_SIZE: u16 = 64;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => Ok(descriptor),
             Err(e) => Err(Error::nom_parse(ParseContext::BlockGroupDescriptor, e)),
         }
     }
 
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, block_bitmap_lo) = le_u32(input)?;
+        let (input, inode_bitmap_lo) = le_u32(input)?;
+        let (input, inode_table_first_block_lo) = le_u32(input)?;
+        let (input, free_blocks_count_lo) = le_u16(input)?;
+        let (input, free_inodes_count_lo) = le_u16(input)?;
+        let (input, used_dirs_count_lo) = le_u16(input)?;
+        let (input, flags) = Flags::parse(input)?;
+        let (input, exclude_bitmap_lo) = le_u32(input)?;
+        let (input, block_bitmap_csum_lo) = le_u16(input)?;
+        let (input, inode_bitmap_csum_lo) = le_u16(input)?;
+        let (input, itable_unused_lo) = le_u16(input)?;
+        let (input, checksum) = le_u16(input)?;
+
+        // The high halves are all present or all missing, depending on the descriptor size
+        let is_64bit = input.len() >= (Self::MAX_SIZE - Self::MIN_SIZE) as usize;
+        let (input, block_bitmap_hi) = cond(is_64bit, le_u32)(input)?;
+        let (input, inode_bitmap_hi) = cond(is_64bit, le_u32)(input)?;
+        let (input, inode_table_first_block_hi) = cond(is_64bit, le_u32)(input)?;
+        let (input, free_blocks_count_hi) = cond(is_64bit, le_u16)(input)?;
+        let (input, free_inodes_count_hi) = cond(is_64bit, le_u16)(input)?;
+        let (input, used_dirs_count_hi) = cond(is_64bit, le_u16)(input)?;
+        let (input, itable_unused_hi) = cond(is_64bit, le_u16)(input)?;
+        let (input, exclude_bitmap_hi) = cond(is_64bit, le_u32)(input)?;
+        let (input, block_bitmap_csum_hi) = cond(is_64bit, le_u16)(input)?;
+        let (input, inode_bitmap_csum_hi) = cond(is_64bit, le_u16)(input)?;
+        let (input, reserved) = cond(is_64bit, le_u32)(input)?;
+
+        Ok((
+            input,
+            Self {
+                block_bitmap_lo,
+                inode_bitmap_lo,
+                inode_table_first_block_lo,
+                free_blocks_count_lo,
+                free_inodes_count_lo,
+                used_dirs_count_lo,
+                flags,
+                exclude_bitmap_lo,
+                block_bitmap_csum_lo,
+                inode_bitmap_csum_lo,
+                itable_unused_lo,
+                checksum,
+                block_bitmap_hi,
+                inode_bitmap_hi,
+                inode_table_first_block_hi,
+                free_blocks_count_hi,
+                free_inodes_count_hi,
+                used_dirs_count_hi,
+                itable_unused_hi,
+                exclude_bitmap_hi,
+                block_bitmap_csum_hi,
+                inode_bitmap_csum_hi,
+                reserved,
+            },
+        ))
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
     pub fn block_bitmap(&self) -> u64 {
         ((self.block_bitmap_hi.unwrap_or(0) as u64) << 32) | (self.block_bitmap_lo as u64)
     }
@@ -88,6 +146,18 @@ impl BlockGroupDescriptor {
     pub fn exclude_bitmap(&self) -> u64 {
         ((self.exclude_bitmap_hi.unwrap_or(0) as u64) << 32) | (self.exclude_bitmap_lo as u64)
     }
+
+    pub fn block_bitmap_checksum(&self) -> u32 {
+        ((self.block_bitmap_csum_hi.unwrap_or(0) as u32) << 16) | (self.block_bitmap_csum_lo as u32)
+    }
+
+    pub fn inode_bitmap_checksum(&self) -> u32 {
+        ((self.inode_bitmap_csum_hi.unwrap_or(0) as u32) << 16) | (self.inode_bitmap_csum_lo as u32)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.checksum
+    }
 }
 
 bitflags! {
@@ -101,7 +171,55 @@ bitflags! {
 
 impl Flags {
     pub fn parse(input: &[u8]) -> nom::IResult<&[u8], Self> {
-        let (input, bits) = nom::number::complete::le_u16(input)?;
+        let (input, bits) = le_u16(input)?;
         Ok((input, Flags::from_bits_truncate(bits)))
     }
 }
+
+/// The group descriptor table, loaded once when a volume is opened
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub(crate) struct GroupTable {
+    groups: Arc<[BlockGroupDescriptor]>,
+}
+
+#[cfg(feature = "std")]
+impl GroupTable {
+    /// Get the byte offset and length of the table on disk
+    pub fn location(superblock: &Superblock) -> Result<(u64, usize)> {
+        let length =
+            superblock.block_group_count() as usize * superblock.descriptor_size() as usize;
+        Ok((superblock.descriptor_offset(0)?, length))
+    }
+
+    /// Parse the table read from `location`
+    pub fn parse(superblock: &Superblock, table: &[u8]) -> Result<Self> {
+        let groups = table
+            .chunks_exact(superblock.descriptor_size() as usize)
+            .map(BlockGroupDescriptor::parse)
+            .collect::<Result<_>>()?;
+        Ok(Self { groups })
+    }
+
+    /// Get the descriptor of a block group
+    pub fn get(&self, bg_index: u32) -> Result<BlockGroupDescriptor> {
+        self.groups
+            .get(bg_index as usize)
+            .copied()
+            .ok_or(Error::InvalidBlockGroup {
+                index: bg_index,
+                count: self.groups.len() as u32,
+            })
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, BlockGroupDescriptor> {
+        self.groups.iter()
+    }
+
+    /// Get the byte offset of an inode on disk
+    pub fn inode_offset(&self, superblock: &Superblock, inode_num: u32) -> Result<u64> {
+        let (bg_index, table_offset) = superblock.inode_location(inode_num)?;
+        let inode_table_block = self.get(bg_index)?.inode_table_first_block();
+        Ok(inode_table_block * superblock.block_size() as u64 + table_offset)
+    }
+}
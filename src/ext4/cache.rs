@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    Volume,
+    ext4::{Result, device::BlockDevice},
+};
+
+const NIL: usize = usize::MAX;
+
+struct LruNode<K, V> {
+    key: K,
+    value: V,
+    prev: usize,
+    next: usize,
+}
+
+/// A fixed-capacity map that evicts the least recently used entry
+///
+/// Entries live in a slab linked in recency order, so lookups, inserts and
+/// evictions are all O(1).
+pub(crate) struct Lru<K, V> {
+    map: HashMap<K, usize>,
+    nodes: Vec<LruNode<K, V>>,
+    head: usize,
+    tail: usize,
+    capacity: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::with_capacity(capacity),
+            nodes: Vec::with_capacity(capacity),
+            head: NIL,
+            tail: NIL,
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get an entry and mark it as the most recently used
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let index = *self.map.get(key)?;
+        self.unlink(index);
+        self.push_front(index);
+        Some(&self.nodes[index].value)
+    }
+
+    /// Insert an entry, evicting the least recently used one when full
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(&index) = self.map.get(&key) {
+            self.nodes[index].value = value;
+            self.unlink(index);
+            self.push_front(index);
+            return;
+        }
+
+        let index = if self.nodes.len() < self.capacity {
+            self.nodes.push(LruNode {
+                key: key.clone(),
+                value,
+                prev: NIL,
+                next: NIL,
+            });
+            self.nodes.len() - 1
+        } else {
+            // Reuse the slot of the least recently used entry
+            let index = self.tail;
+            self.unlink(index);
+            let node = &mut self.nodes[index];
+            self.map.remove(&node.key);
+            node.key = key.clone();
+            node.value = value;
+            index
+        };
+
+        self.map.insert(key, index);
+        self.push_front(index);
+    }
+
+    /// Remove an entry, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let index = self.map.remove(key)?;
+        self.unlink(index);
+
+        // Move the last node into the freed slot so the slab stays dense
+        let last = self.nodes.len() - 1;
+        if index != last {
+            let (prev, next) = (self.nodes[last].prev, self.nodes[last].next);
+            match prev {
+                NIL => self.head = index,
+                prev => self.nodes[prev].next = index,
+            }
+            match next {
+                NIL => self.tail = index,
+                next => self.nodes[next].prev = index,
+            }
+            if let Some(slot) = self.map.get_mut(&self.nodes[last].key) {
+                *slot = index;
+            }
+        }
+        Some(self.nodes.swap_remove(index).value)
+    }
+
+    /// Keep only the entries matching a predicate
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let removed: Vec<K> = self
+            .nodes
+            .iter()
+            .filter(|node| !keep(&node.key, &node.value))
+            .map(|node| node.key.clone())
+            .collect();
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.nodes.clear();
+        self.head = NIL;
+        self.tail = NIL;
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = self.head;
+        match self.head {
+            NIL => self.tail = index,
+            head => self.nodes[head].prev = index,
+        }
+        self.head = index;
+    }
+}
+
+/// Hit and miss counters of a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of entries currently cached
+    pub len: usize,
+    /// Maximum number of entries the cache holds
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Get the fraction of lookups served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// A thread-safe LRU cache with hit and miss statistics, shared by clones of a `Volume`
+pub(crate) struct SharedCache<K, V> {
+    entries: Mutex<Lru<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SharedCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(Lru::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a cached value, counting the lookup as a hit or a miss
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.lock().get(key).cloned();
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.lock().insert(key, value);
+    }
+
+    /// Drop a single entry
+    pub fn remove(&self, key: &K) {
+        self.lock().remove(key);
+    }
+
+    /// Drop every entry matching a predicate
+    pub fn invalidate(&self, mut matches: impl FnMut(&K) -> bool) {
+        self.lock().retain(|key, _| !matches(key));
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.lock();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: entries.len(),
+            capacity: entries.capacity(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Lru<K, V>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K, V> fmt::Debug for SharedCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedCache")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// A cache of filesystem blocks, keyed by block number
+pub(crate) type BlockCache = SharedCache<u64, Arc<[u8]>>;
+
+impl BlockCache {
+    /// Get a block, reading it from the device on a miss
+    pub fn block<D: BlockDevice + ?Sized>(
+        &self,
+        device: &D,
+        block_num: u64,
+        block_size: u32,
+    ) -> Result<Arc<[u8]>> {
+        if let Some(block) = self.get(&block_num) {
+            return Ok(block);
+        }
+
+        // Read without holding the lock, a concurrent miss only costs a second read
+        let data = device.read_bytes(block_num * block_size as u64, block_size as usize)?;
+        let block: Arc<[u8]> = Arc::from(&*data);
+        self.insert(block_num, Arc::clone(&block));
+        Ok(block)
+    }
+}
+
+/// A block read either straight from the device or from the block cache
+pub(crate) enum BlockData<'a> {
+    Device(Cow<'a, [u8]>),
+    Cached(Arc<[u8]>),
+}
+
+impl Deref for BlockData<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BlockData::Device(data) => data,
+            BlockData::Cached(data) => data,
+        }
+    }
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Cache up to `capacity` metadata blocks, shared by all clones of the volume
+    ///
+    /// Inode tables, extent index, indirect, xattr and directory blocks go
+    /// through the cache. File data does not, so extracting large files does
+    /// not evict the metadata. Devices that live in memory are never cached.
+    pub fn with_block_cache(mut self, capacity: usize) -> Self {
+        self.block_cache = Some(Arc::new(BlockCache::new(capacity)));
+        self
+    }
+
+    /// Get the hit and miss statistics of the block cache, if enabled
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.block_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Get the block cache, unless the device is already in memory
+    pub(crate) fn block_cache(&self) -> Option<Arc<BlockCache>> {
+        self.block_cache
+            .clone()
+            .filter(|_| self.device().as_bytes().is_none())
+    }
+
+    /// Read a metadata structure, through the block cache when it fits in one block
+    pub(crate) fn read_metadata(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        let block_size = self.block_size() as u64;
+        let start = (offset % block_size) as usize;
+        match self.block_cache() {
+            Some(cache) if start + len <= block_size as usize => {
+                let block = cache.block(self.device(), offset / block_size, self.block_size())?;
+                Ok(Cow::Owned(block[start..start + len].to_vec()))
+            }
+            _ => self.read_bytes(offset, len),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use alloc::vec::Vec;
+
+use crate::ext4::superblock::{IncompatibleFeatures, ReadOnlyCompatibleFeatures, Superblock};
+
+/// How `Volume` reacts to features it cannot handle correctly
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FeaturePolicy {
+    /// Refuse to open images whose data would be unreadable, wrong or stale
+    Strict,
+    /// Open anyway and record the problems in a `CompatibilityReport`
+    #[default]
+    Permissive,
+}
+
+/// What goes wrong when reading an image that uses an unsupported feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Impact {
+    /// Some information is missing, but what is read is correct
+    Degraded,
+    /// Metadata may not reflect the latest changes to the filesystem
+    Stale,
+    /// Some file names, contents or metadata will be read incorrectly
+    IncorrectData,
+    /// The image cannot be read meaningfully
+    Unreadable,
+}
+
+/// A feature flag the reader does not fully support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Incompatible(IncompatibleFeatures),
+    ReadOnlyCompatible(ReadOnlyCompatibleFeatures),
+}
+
+impl core::fmt::Display for Feature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (names, bits) = match self {
+            Feature::Incompatible(feature) => (
+                feature
+                    .iter_names()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+                feature.bits(),
+            ),
+            Feature::ReadOnlyCompatible(feature) => (
+                feature
+                    .iter_names()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+                feature.bits(),
+            ),
+        };
+
+        if names.is_empty() {
+            write!(f, "unknown feature bits {:#x}", bits)
+        } else {
+            write!(f, "{}", names.join("|"))
+        }
+    }
+}
+
+/// An unsupported feature found in the superblock and its impact on reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedFeature {
+    feature: Feature,
+    impact: Impact,
+    description: &'static str,
+}
+
+impl UnsupportedFeature {
+    pub fn feature(&self) -> Feature {
+        self.feature
+    }
+
+    pub fn impact(&self) -> Impact {
+        self.impact
+    }
+
+    /// Human readable explanation of what goes wrong
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+}
+
+impl core::fmt::Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} ({:?}): {}",
+            self.feature, self.impact, self.description
+        )
+    }
+}
+
+/// Result of checking the superblock feature sets against what the reader supports
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompatibilityReport {
+    unsupported: Vec<UnsupportedFeature>,
+}
+
+impl CompatibilityReport {
+    const INCOMPATIBLE: [(IncompatibleFeatures, Impact, &'static str); 9] = [
+        (
+            IncompatibleFeatures::Compression,
+            Impact::IncorrectData,
+            "compressed file contents are returned without decompression",
+        ),
+        (
+            IncompatibleFeatures::NeedsRecovery,
+            Impact::Stale,
+            "the journal has not been replayed, so recent changes are missing",
+        ),
+        (
+            IncompatibleFeatures::JournalDevice,
+            Impact::Unreadable,
+            "the image is an external journal device, not a filesystem",
+        ),
+        (
+            IncompatibleFeatures::MetaBlockGroups,
+            Impact::IncorrectData,
+            "group descriptors past the first meta group are looked up in the wrong place",
+        ),
+        (
+            IncompatibleFeatures::ExtendedAttributeInodes,
+            Impact::Degraded,
+            "extended attribute values stored in separate inodes are not read",
+        ),
+        (
+            IncompatibleFeatures::DirectoryData,
+            Impact::IncorrectData,
+            "file types of directory entries carrying extra data are misread",
+        ),
+        (
+            IncompatibleFeatures::InlineData,
+            Impact::IncorrectData,
+            "files stored inside their inode are read as if they used block maps",
+        ),
+        (
+            IncompatibleFeatures::EncryptedInodes,
+            Impact::Unreadable,
+            "encrypted file names and contents are returned as ciphertext",
+        ),
+        (
+            IncompatibleFeatures::Casefold,
+            Impact::Degraded,
+            "path lookups are case-sensitive even in case-insensitive directories",
+        ),
+    ];
+
+    const READ_ONLY: [(ReadOnlyCompatibleFeatures, Impact, &'static str); 1] = [(
+        ReadOnlyCompatibleFeatures::OrphanPresent,
+        Impact::Degraded,
+        "orphaned inodes waiting to be deleted are still listed unless orphans are processed",
+    )];
+
+    /// Check the feature sets of a superblock
+    pub fn new(superblock: &Superblock) -> Self {
+        let incompatible = superblock.features_incompatible();
+        let read_only = superblock.features_read_only();
+        let mut unsupported = Vec::new();
+
+        for (feature, impact, description) in Self::INCOMPATIBLE {
+            if incompatible.contains(feature) {
+                unsupported.push(UnsupportedFeature {
+                    feature: Feature::Incompatible(feature),
+                    impact,
+                    description,
+                });
+            }
+        }
+
+        let unknown = IncompatibleFeatures::from_bits_retain(
+            incompatible.bits() & !IncompatibleFeatures::all().bits(),
+        );
+        if !unknown.is_empty() {
+            unsupported.push(UnsupportedFeature {
+                feature: Feature::Incompatible(unknown),
+                impact: Impact::IncorrectData,
+                description: "unknown incompatible features change the on-disk format",
+            });
+        }
+
+        for (feature, impact, description) in Self::READ_ONLY {
+            if read_only.contains(feature) {
+                unsupported.push(UnsupportedFeature {
+                    feature: Feature::ReadOnlyCompatible(feature),
+                    impact,
+                    description,
+                });
+            }
+        }
+
+        let unknown = ReadOnlyCompatibleFeatures::from_bits_retain(
+            read_only.bits() & !ReadOnlyCompatibleFeatures::all().bits(),
+        );
+        if !unknown.is_empty() {
+            unsupported.push(UnsupportedFeature {
+                feature: Feature::ReadOnlyCompatible(unknown),
+                impact: Impact::Degraded,
+                description: "unknown read-only features may carry information that is ignored",
+            });
+        }
+
+        Self { unsupported }
+    }
+
+    /// Get every unsupported feature found
+    pub fn unsupported(&self) -> &[UnsupportedFeature] {
+        &self.unsupported
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+
+    /// Check if the image can be read without wrong or stale data
+    pub fn is_safe(&self) -> bool {
+        self.unsupported
+            .iter()
+            .all(|feature| feature.impact == Impact::Degraded)
+    }
+}
+
+impl core::fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (index, feature) in self.unsupported.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", feature)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::os::unix::fs::FileExt;
+
+use crate::{Result, ext4::device::BlockDevice};
+
+/// How the data of a file was copied out of the image
+///
+/// Methods are ordered from cheapest to most expensive. The discriminants
+/// follow that order, so they can index per-method counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CopyMethod {
+    /// The output shares the blocks of the image (`FICLONERANGE`)
+    Reflink,
+    /// The kernel copied the data without going through userspace (`copy_file_range`)
+    CopyFileRange,
+    /// The data was read into memory and written back
+    Buffered,
+}
+
+/// Copies byte ranges from a device backed by a file into an output file
+///
+/// Each range is first reflinked, then copied with `copy_file_range`, then
+/// copied through a buffer. A method that fails once is not tried again.
+pub(crate) struct RangeCopier<'a, D: BlockDevice + AsFd> {
+    device: &'a D,
+    output: &'a fs::File,
+    block_size: u64,
+    reflink: bool,
+    copy_file_range: bool,
+    slowest: CopyMethod,
+    buffer: Vec<u8>,
+}
+
+impl<'a, D: BlockDevice + AsFd> RangeCopier<'a, D> {
+    /// Largest buffer used by the buffered fallback
+    const BUFFER_SIZE: usize = 1024 * 1024;
+
+    pub fn new(device: &'a D, output: &'a fs::File, block_size: u32) -> Self {
+        Self {
+            device,
+            output,
+            block_size: block_size as u64,
+            reflink: true,
+            copy_file_range: true,
+            slowest: CopyMethod::Reflink,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Get the most expensive method used so far
+    pub fn slowest(&self) -> CopyMethod {
+        self.slowest
+    }
+
+    /// Copy `length` bytes at `source_offset` on the device to `output_offset` in the output
+    ///
+    /// Reflinks cover whole blocks, so the output may grow past the end of
+    /// the range and has to be truncated once every range is copied.
+    ///
+    /// Ranges are only aligned to the ext4 block size, while `FICLONERANGE`
+    /// needs them aligned to the block size of the host filesystem. Images
+    /// with 1 KiB blocks therefore never reflink on a 4 KiB host and fall back
+    /// to `copy_file_range`.
+    pub fn copy(&mut self, source_offset: u64, output_offset: u64, length: u64) -> Result<()> {
+        let source = self.device.as_fd();
+        let output = self.output.as_fd();
+
+        if self.reflink {
+            let aligned = length.next_multiple_of(self.block_size);
+            match clone_range(source, source_offset, output, output_offset, aligned) {
+                Ok(()) => return Ok(()),
+                Err(_) => self.reflink = false,
+            }
+        }
+
+        let mut done = 0;
+        if self.copy_file_range {
+            self.slowest = self.slowest.max(CopyMethod::CopyFileRange);
+            while done < length {
+                match copy_range(
+                    source,
+                    source_offset + done,
+                    output,
+                    output_offset + done,
+                    length - done,
+                ) {
+                    Ok(copied) if copied > 0 => done += copied,
+                    // Copy what is left through userspace
+                    Ok(_) | Err(_) => {
+                        self.copy_file_range = false;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if done < length {
+            self.slowest = CopyMethod::Buffered;
+        }
+        while done < length {
+            let chunk = std::cmp::min(length - done, Self::BUFFER_SIZE as u64) as usize;
+            self.buffer.resize(chunk, 0);
+            self.device
+                .read_at(source_offset + done, &mut self.buffer)?;
+            self.output
+                .write_all_at(&self.buffer, output_offset + done)?;
+            done += chunk as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Share `length` bytes of `source` with `output`, see ioctl_ficlonerange(2)
+fn clone_range(
+    source: BorrowedFd<'_>,
+    source_offset: u64,
+    output: BorrowedFd<'_>,
+    output_offset: u64,
+    length: u64,
+) -> io::Result<()> {
+    let range = libc::file_clone_range {
+        src_fd: source.as_raw_fd() as i64,
+        src_offset: source_offset,
+        src_length: length,
+        dest_offset: output_offset,
+    };
+    // SAFETY: both descriptors are borrowed for the duration of the call and
+    // `range` is a valid `file_clone_range`
+    match unsafe { libc::ioctl(output.as_raw_fd(), libc::FICLONERANGE, &range) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Copy up to `length` bytes in the kernel, returning how many were copied
+fn copy_range(
+    source: BorrowedFd<'_>,
+    source_offset: u64,
+    output: BorrowedFd<'_>,
+    output_offset: u64,
+    length: u64,
+) -> io::Result<u64> {
+    let mut source_offset = source_offset as libc::off64_t;
+    let mut output_offset = output_offset as libc::off64_t;
+    let length = usize::try_from(length).unwrap_or(usize::MAX);
+    // SAFETY: both descriptors are borrowed for the duration of the call and
+    // the offsets point to live locals
+    match unsafe {
+        libc::copy_file_range(
+            source.as_raw_fd(),
+            &mut source_offset,
+            output.as_raw_fd(),
+            &mut output_offset,
+            length,
+            0,
+        )
+    } {
+        -1 => Err(io::Error::last_os_error()),
+        copied => Ok(copied as u64),
+    }
+}
@@ -0,0 +1,82 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{
+    Directory, Result, Volume,
+    ext4::{
+        cache::{CacheStats, SharedCache},
+        device::BlockDevice,
+        inode::Inode,
+    },
+};
+
+/// Cached directory lookups keyed by parent inode and name, `None` marks a missing name
+pub(crate) type DentryCache = SharedCache<(u32, Box<[u8]>), Option<u32>>;
+
+impl<D: BlockDevice> Volume<D> {
+    /// Cache up to `capacity` path component lookups, shared by all clones of the volume
+    ///
+    /// Each entry maps a directory inode and a name to the inode it refers to,
+    /// or records that the name does not exist.
+    pub fn with_dentry_cache(mut self, capacity: usize) -> Self {
+        self.dentry_cache = Some(Arc::new(DentryCache::new(capacity)));
+        self
+    }
+
+    /// Get the hit and miss statistics of the dentry cache, if enabled
+    pub fn dentry_cache_stats(&self) -> Option<CacheStats> {
+        self.dentry_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Forget the cached lookup of `name` in the directory with inode number `parent`
+    ///
+    /// Anything that adds, removes or renames a directory entry must call this
+    /// or `invalidate_directory`, so lookups do not return stale inodes.
+    pub fn invalidate_dentry(&self, parent: u32, name: impl AsRef<OsStr>) {
+        if let Some(cache) = &self.dentry_cache {
+            cache.remove(&(parent, Box::from(name.as_ref().as_encoded_bytes())));
+        }
+    }
+
+    /// Forget every cached lookup in the directory with inode number `parent`
+    pub fn invalidate_directory(&self, parent: u32) {
+        if let Some(cache) = &self.dentry_cache {
+            cache.invalidate(|(dir, _)| *dir == parent);
+        }
+    }
+
+    /// Forget every cached lookup
+    pub fn clear_dentry_cache(&self) {
+        if let Some(cache) = &self.dentry_cache {
+            cache.clear();
+        }
+    }
+
+    /// Look up a name in a directory, through the dentry cache when enabled
+    pub(crate) fn lookup_child(
+        &self,
+        parent_num: u32,
+        parent: &Inode,
+        parent_path: &Path,
+        name: &[u8],
+    ) -> Result<Option<u32>> {
+        let lookup = || {
+            let directory = Directory::new(self, parent.clone(), parent_path)?;
+            Ok(directory.find_bytes(name)?.map(|entry| entry.inode))
+        };
+
+        let Some(cache) = &self.dentry_cache else {
+            return lookup();
+        };
+
+        let key = (parent_num, Box::from(name));
+        if let Some(child) = cache.get(&key) {
+            return Ok(child);
+        }
+
+        let child = lookup()?;
+        cache.insert(key, child);
+        Ok(child)
+    }
+}
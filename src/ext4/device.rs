@@ -0,0 +1,263 @@
+use alloc::{borrow::Cow, boxed::Box, sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    sync::Mutex,
+};
+
+use crate::{Error, Result};
+
+/// A source of filesystem data that supports reads at arbitrary offsets
+///
+/// `Volume` is generic over this trait, so images can be read from files,
+/// memory or any custom backend (sparse images, network storage, caches...).
+pub trait BlockDevice {
+    /// Fill `buf` with the bytes starting at `offset`
+    ///
+    /// Reading past the end of the device is an error.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Get the size of the device in bytes
+    fn size(&self) -> Result<u64>;
+
+    /// Get the preferred I/O size of the device, if known
+    fn block_size_hint(&self) -> Option<u32> {
+        None
+    }
+
+    /// Get the whole device as a byte slice, if it lives in memory
+    ///
+    /// Devices that return `Some` are read without copying.
+    fn as_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Get `len` bytes starting at `offset`, borrowed if the device lives in memory
+    fn read_bytes(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        if let Some(bytes) = self.as_bytes() {
+            return usize::try_from(offset)
+                .ok()
+                .and_then(|start| bytes.get(start..start.checked_add(len)?))
+                .map(Cow::Borrowed)
+                .ok_or(Error::OutOfBounds { offset, len });
+        }
+
+        let mut buffer = vec![0u8; len];
+        self.read_at(offset, &mut buffer)?;
+        Ok(Cow::Owned(buffer))
+    }
+}
+
+impl BlockDevice for [u8] {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        buf.copy_from_slice(&self.read_bytes(offset, buf.len())?);
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl BlockDevice for Vec<u8> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.as_slice().read_at(offset, buf)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+/// A memory-mapped image, read without copying
+#[cfg(feature = "mmap")]
+impl BlockDevice for memmap2::Mmap {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl BlockDevice for std::fs::File {
+    #[cfg(unix)]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+        Ok(self.read_exact_at(buf, offset)?)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, mut offset: u64, mut buf: &mut [u8]) -> Result<()> {
+        use std::os::windows::fs::FileExt;
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => {
+                    return Err(Error::OutOfBounds {
+                        offset,
+                        len: buf.len(),
+                    });
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    #[cfg(unix)]
+    fn block_size_hint(&self) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+        self.metadata()
+            .ok()
+            .and_then(|metadata| u32::try_from(metadata.blksize()).ok())
+    }
+}
+
+/// A single seekable reader shared behind a lock
+#[cfg(feature = "std")]
+impl<R: Read + Seek> BlockDevice for Mutex<R> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut reader = self.lock().unwrap_or_else(|e| e.into_inner());
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(reader.read_exact(buf)?)
+    }
+
+    fn size(&self) -> Result<u64> {
+        let mut reader = self.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(reader.seek(SeekFrom::End(0))?)
+    }
+}
+
+impl<T: BlockDevice + ?Sized> BlockDevice for &T {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn size(&self) -> Result<u64> {
+        (**self).size()
+    }
+
+    fn block_size_hint(&self) -> Option<u32> {
+        (**self).block_size_hint()
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        (**self).as_bytes()
+    }
+}
+
+impl<T: BlockDevice + ?Sized> BlockDevice for Box<T> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn size(&self) -> Result<u64> {
+        (**self).size()
+    }
+
+    fn block_size_hint(&self) -> Option<u32> {
+        (**self).block_size_hint()
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        (**self).as_bytes()
+    }
+}
+
+impl<T: BlockDevice + ?Sized> BlockDevice for Arc<T> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn size(&self) -> Result<u64> {
+        (**self).size()
+    }
+
+    fn block_size_hint(&self) -> Option<u32> {
+        (**self).block_size_hint()
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        (**self).as_bytes()
+    }
+}
+
+/// Adapter for the reader factory style: opens readers on demand and reuses them
+///
+/// Readers are kept in a pool, so concurrent reads each get their own reader
+/// while sequential reads do not reopen the image.
+#[cfg(feature = "std")]
+pub struct ReaderFactory<R, F> {
+    factory: F,
+    pool: Mutex<Vec<R>>,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read + Seek, F: Fn() -> io::Result<R>> ReaderFactory<R, F> {
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory,
+            pool: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn with_reader<T>(&self, f: impl FnOnce(&mut R) -> io::Result<T>) -> Result<T> {
+        let pooled = self.pool.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let mut reader = match pooled {
+            Some(reader) => reader,
+            None => (self.factory)()?,
+        };
+
+        // A reader that failed may be left in a bad state, so only keep good ones
+        let result = f(&mut reader)?;
+        self.pool
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(reader);
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read + Seek, F: Fn() -> io::Result<R>> BlockDevice for ReaderFactory<R, F> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.with_reader(|reader| {
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(buf)
+        })
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.with_reader(|reader| reader.seek(SeekFrom::End(0)))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R, F> std::fmt::Debug for ReaderFactory<R, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReaderFactory").finish_non_exhaustive()
+    }
+}
@@ -1,38 +1,35 @@
-use std::io::{Read, Seek};
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
 use crate::{
     DirectoryWalker, Error, Result, Volume,
-    ext4::{DirectoryEntry, InodeReader, inode::Inode},
+    ext4::{
+        DirectoryEntry, InodeReader, device::BlockDevice, extent::ExtentMap, inode::Inode,
+        metadata::Metadata,
+    },
 };
 
 /// Represents a directory in the ext4 filesystem
-pub struct Directory<R: Read + Seek, F: Fn() -> R> {
-    pub(crate) volume: Volume<R, F>,
+pub struct Directory<D: BlockDevice> {
+    pub(crate) volume: Volume<D>,
     path: PathBuf,
     inode: Inode,
-    entries: Vec<DirectoryEntry>,
+    metadata: Metadata,
 }
 
-impl<R: Read + Seek, F: Fn() -> R> Directory<R, F> {
+impl<D: BlockDevice> Directory<D> {
     /// Create a new Directory from a volume, inode, and path
-    pub(crate) fn new(
-        volume: &Volume<R, F>,
-        inode: Inode,
-        path: impl Into<PathBuf>,
-    ) -> Result<Self> {
+    pub(crate) fn new(volume: &Volume<D>, inode: Inode, path: impl Into<PathBuf>) -> Result<Self> {
         let path = path.into();
         if !inode.is_directory() {
             return Err(Error::NotADirectory(format!("{}", path.display())));
         }
 
-        let entries = Self::parse_entries(volume, &inode)?;
-
         Ok(Self {
             volume: volume.clone(),
             path,
+            metadata: Metadata::new(&inode, volume.superblock()),
             inode,
-            entries,
         })
     }
 
@@ -41,87 +38,195 @@ impl<R: Read + Seek, F: Fn() -> R> Directory<R, F> {
         &self.inode
     }
 
+    /// Get the metadata of this directory
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
     /// Get the path of this directory
     pub fn path(&self) -> &Path {
         &self.path
     }
 
-    pub fn entries(&self) -> &[DirectoryEntry] {
-        &self.entries
+    /// Iterate over the entries, reading the directory one block at a time
+    pub fn entries(&self) -> Entries<D> {
+        Entries::new(&self.volume, self.inode.clone())
     }
 
-    pub fn entries_mut(&mut self) -> &mut Vec<DirectoryEntry> {
-        &mut self.entries
+    /// Create a walker for recursive directory traversal
+    pub fn walk(self) -> DirectoryWalker<D> {
+        DirectoryWalker::new(self)
     }
 
-    /// Parse directory entries from raw data
-    fn parse_entries(volume: &Volume<R, F>, inode: &Inode) -> Result<Vec<DirectoryEntry>> {
-        let data = InodeReader::new(volume).read_all(inode)?;
-        let mut entries = Vec::new();
-        let mut offset = 0;
+    /// Create a walker that traverses directories in parallel on the rayon pool
+    #[cfg(feature = "parallel")]
+    pub fn par_walk(self) -> crate::ext4::ParallelWalker<D>
+    where
+        D: Send + Sync,
+    {
+        crate::ext4::ParallelWalker::new(self)
+    }
 
-        while offset < data.len() {
-            if offset + DirectoryEntry::HEADER_SIZE > data.len() {
-                break;
+    /// Find an entry by name
+    pub fn find(&self, name: impl AsRef<OsStr>) -> Result<Option<DirectoryEntry>> {
+        self.find_bytes(name.as_ref().as_encoded_bytes())
+    }
+
+    /// Find an entry by its raw name bytes
+    pub fn find_bytes(&self, name: &[u8]) -> Result<Option<DirectoryEntry>> {
+        for entry in self.entries() {
+            let entry = entry?;
+            if entry.name_bytes() == name {
+                return Ok(Some(entry));
             }
+        }
+        Ok(None)
+    }
+}
 
-            let inode_num = u32::from_le_bytes(
-                data[offset..offset + 4]
-                    .try_into()
-                    .map_err(|_| Error::CorruptedDirectoryEntry(offset))?,
-            );
+impl<D: BlockDevice> IntoIterator for Directory<D> {
+    type Item = Result<DirectoryEntry>;
+    type IntoIter = Entries<D>;
 
-            let entry_len = u16::from_le_bytes([data[offset + 4], data[offset + 5]]);
+    fn into_iter(self) -> Self::IntoIter {
+        Entries::new(&self.volume, self.inode)
+    }
+}
 
-            if entry_len == 0 || entry_len as usize > data.len() - offset {
-                break;
-            }
+/// Lazy iterator over the entries of a directory
+///
+/// Only one directory block is held in memory at a time.
+pub struct Entries<D: BlockDevice> {
+    reader: InodeReader<D>,
+    inode: Inode,
+    extents: Option<ExtentMap>,
+    cursor: DirectoryCursor,
+}
 
-            if inode_num != 0 {
-                let name_len = data[offset + 6];
-                let inode_type = data[offset + 7];
-
-                let mut name = [0u8; DirectoryEntry::MAX_NAME_LEN];
-                let actual_name_len = DirectoryEntry::MAX_NAME_LEN.min(name_len as usize);
-                if offset + DirectoryEntry::HEADER_SIZE + actual_name_len <= data.len() {
-                    name[..actual_name_len].copy_from_slice(
-                        &data[offset + DirectoryEntry::HEADER_SIZE
-                            ..offset + DirectoryEntry::HEADER_SIZE + actual_name_len],
-                    );
+impl<D: BlockDevice> Entries<D> {
+    fn new(volume: &Volume<D>, inode: Inode) -> Self {
+        Self {
+            reader: InodeReader::new(volume),
+            cursor: DirectoryCursor::new(inode.size(), volume.block_size()),
+            extents: None,
+            inode,
+        }
+    }
+}
+
+impl<D: BlockDevice> Iterator for Entries<D> {
+    type Item = Result<DirectoryEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.cursor.step() {
+                CursorStep::Entry(entry) => return Some(Ok(entry)),
+                CursorStep::End => return None,
+                CursorStep::NeedBlock(offset) => {
+                    // Map the extents once instead of walking the tree for every block
+                    if self.extents.is_none() {
+                        match self.reader.extent_map(&self.inode) {
+                            Ok(extents) => self.extents = Some(extents),
+                            Err(e) => {
+                                self.cursor.finish();
+                                return Some(Err(e));
+                            }
+                        }
+                    }
+
+                    let buffer = self.cursor.block_buffer();
+                    match self.reader.read_into_mapped(
+                        &self.inode,
+                        self.extents.as_ref(),
+                        offset,
+                        buffer,
+                    ) {
+                        Ok(length) => self.cursor.block_loaded(length),
+                        Err(e) => {
+                            self.cursor.finish();
+                            return Some(Err(e));
+                        }
+                    }
                 }
+            }
+        }
+    }
+}
+
+/// What a `DirectoryCursor` needs or produced next
+pub(crate) enum CursorStep {
+    Entry(DirectoryEntry),
+    /// The block at this byte offset in the directory must be loaded
+    NeedBlock(u64),
+    End,
+}
+
+/// Position of a scan through the blocks of a directory, independent of how blocks are read
+pub(crate) struct DirectoryCursor {
+    size: u64,
+    block_size: u64,
+    block: u64,
+    buffer: Vec<u8>,
+    offset: usize,
+    done: bool,
+}
+
+impl DirectoryCursor {
+    pub fn new(size: u64, block_size: u32) -> Self {
+        Self {
+            size,
+            block_size: block_size as u64,
+            block: 0,
+            buffer: Vec::new(),
+            offset: 0,
+            done: false,
+        }
+    }
 
-                entries.push(DirectoryEntry {
-                    inode: inode_num,
-                    entry_len,
-                    name_len,
-                    inode_type,
-                    name,
-                });
+    /// Produce the next used entry, or ask for the next block to be loaded
+    pub fn step(&mut self) -> CursorStep {
+        while !self.done {
+            if self.offset >= self.buffer.len() {
+                let position = self.block * self.block_size;
+                if position >= self.size {
+                    break;
+                }
+                return CursorStep::NeedBlock(position);
             }
 
-            offset += entry_len as usize;
+            let block = self.block - 1;
+            match DirectoryEntry::parse(&self.buffer, block, self.offset) {
+                Ok((entry_len, entry)) => {
+                    self.offset += entry_len;
+                    if let Some(entry) = entry {
+                        return CursorStep::Entry(entry);
+                    }
+                }
+                // The rest of the directory cannot be trusted after a bad record
+                // length, so it ends there like it always has
+                Err(_) => break,
+            }
         }
 
-        Ok(entries)
+        self.done = true;
+        CursorStep::End
     }
 
-    /// Create a walker for recursive directory traversal
-    pub fn walk(self) -> DirectoryWalker<R, F> {
-        DirectoryWalker::new(self)
+    /// Get the buffer the requested block should be read into
+    pub fn block_buffer(&mut self) -> &mut [u8] {
+        self.buffer.resize(self.block_size as usize, 0);
+        &mut self.buffer
     }
 
-    pub fn find(&self, name: &str) -> Option<&DirectoryEntry> {
-        self.entries()
-            .into_iter()
-            .find(|entry| entry.name_str() == name)
+    /// Mark the requested block as loaded with `length` valid bytes
+    pub fn block_loaded(&mut self, length: usize) {
+        self.buffer.truncate(length);
+        self.block += 1;
+        self.offset = 0;
     }
-}
 
-impl<R: Read + Seek, F: Fn() -> R> IntoIterator for Directory<R, F> {
-    type Item = DirectoryEntry;
-    type IntoIter = std::vec::IntoIter<DirectoryEntry>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        self.entries.into_iter()
+    /// Stop the scan, such as after failing to read a block
+    pub fn finish(&mut self) {
+        self.done = true;
     }
 }
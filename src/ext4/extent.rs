@@ -1,13 +1,19 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::ops::Range;
+
 use crate::{Error, ParseContext, Result};
-use nom::Finish;
-use nom_derive::{NomLE, Parse};
+use nom::{
+    Finish, IResult,
+    combinator::verify,
+    number::complete::{le_u16, le_u32},
+};
 
 use super::Ext4Lblk;
 
-#[derive(Debug, Default, Clone, Copy, NomLE)]
+#[derive(Debug, Default, Clone, Copy)]
 #[repr(C)]
 pub struct ExtentHeader {
-    #[nom(Verify = "*magic == 0xF30A")]
     magic: u16,
     entries_count: u16,
     max_entries_count: u16,
@@ -19,12 +25,30 @@ impl ExtentHeader {
     pub const SIZE: usize = 12;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => Ok(descriptor),
             Err(e) => Err(Error::nom_parse(ParseContext::ExtentHeader, e)),
         }
     }
 
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, magic) = verify(le_u16, |magic| *magic == 0xF30A)(input)?;
+        let (input, entries_count) = le_u16(input)?;
+        let (input, max_entries_count) = le_u16(input)?;
+        let (input, depth) = le_u16(input)?;
+        let (input, generation) = le_u32(input)?;
+        Ok((
+            input,
+            Self {
+                magic,
+                entries_count,
+                max_entries_count,
+                depth,
+                generation,
+            },
+        ))
+    }
+
     pub fn entries_count(&self) -> u16 {
         self.entries_count
     }
@@ -34,7 +58,7 @@ impl ExtentHeader {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, NomLE)]
+#[derive(Debug, Default, Clone, Copy)]
 #[repr(C)]
 pub struct ExtentIndex {
     first_block: u32,
@@ -48,18 +72,34 @@ impl ExtentIndex {
     pub const MAX_INDEX_COUNT: usize = 340;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => Ok(descriptor),
             Err(e) => Err(Error::nom_parse(ParseContext::ExtentIndex, e)),
         }
     }
 
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, first_block) = le_u32(input)?;
+        let (input, leaf_lo) = le_u32(input)?;
+        let (input, leaf_hi) = le_u16(input)?;
+        let (input, padding) = le_u16(input)?;
+        Ok((
+            input,
+            Self {
+                first_block,
+                leaf_lo,
+                leaf_hi,
+                padding,
+            },
+        ))
+    }
+
     pub fn leaf_block(&self) -> u64 {
         ((self.leaf_hi as u64) << 32) | (self.leaf_lo as u64)
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, NomLE)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct Extent {
     first_block: u32,
@@ -75,12 +115,28 @@ impl Extent {
     pub const EXT_MAX_BLOCKS: Ext4Lblk = u32::MAX;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => Ok(descriptor),
             Err(e) => Err(Error::nom_parse(ParseContext::Extent, e)),
         }
     }
 
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, first_block) = le_u32(input)?;
+        let (input, block_count) = le_u16(input)?;
+        let (input, start_hi) = le_u16(input)?;
+        let (input, start_lo) = le_u32(input)?;
+        Ok((
+            input,
+            Self {
+                first_block,
+                block_count,
+                start_hi,
+                start_lo,
+            },
+        ))
+    }
+
     pub fn first_block(&self) -> u64 {
         self.first_block as u64
     }
@@ -100,4 +156,176 @@ impl Extent {
             self.block_count
         }
     }
+
+    /// Get the logical block just past the end of this extent
+    pub fn end_block(&self) -> u64 {
+        self.first_block() + self.get_actual_len() as u64
+    }
+}
+
+/// The leaf extents of an inode, sorted by logical block
+#[derive(Debug, Default, Clone)]
+pub struct ExtentMap {
+    extents: Vec<Extent>,
+}
+
+impl ExtentMap {
+    pub fn new(mut extents: Vec<Extent>) -> Self {
+        extents.sort_unstable_by_key(Extent::first_block);
+        Self { extents }
+    }
+
+    /// Get all extents in logical block order
+    pub fn extents(&self) -> &[Extent] {
+        &self.extents
+    }
+
+    /// Get the extents covering any of the logical blocks in `start..end`
+    pub fn overlapping(&self, start: u64, end: u64) -> &[Extent] {
+        let first = self
+            .extents
+            .partition_point(|extent| extent.end_block() <= start);
+        let last =
+            first + self.extents[first..].partition_point(|extent| extent.first_block() < end);
+        &self.extents[first..last]
+    }
+
+    /// Map a read of `length` bytes at `offset` to the parts of the buffer stored on disk
+    ///
+    /// Yields the range of the buffer to fill and the byte offset on the
+    /// device to fill it from. Holes and unwritten extents are skipped, so
+    /// those parts of the buffer should be zeroed.
+    #[cfg(feature = "std")]
+    pub(crate) fn segments(
+        &self,
+        offset: u64,
+        length: usize,
+        block_size: u32,
+    ) -> impl Iterator<Item = (Range<usize>, u64)> + '_ {
+        let block_size = block_size as u64;
+        let end = offset + length as u64;
+        self.overlapping(offset / block_size, end.div_ceil(block_size))
+            .iter()
+            .filter(|extent| !extent.is_unwritten())
+            .map(move |extent| {
+                let extent_start = extent.first_block() * block_size;
+                let extent_end = extent.end_block() * block_size;
+                let read_start = core::cmp::max(offset, extent_start);
+                let read_end = core::cmp::min(end, extent_end);
+                let physical = extent.start_block() * block_size + (read_start - extent_start);
+                (
+                    (read_start - offset) as usize..(read_end - offset) as usize,
+                    physical,
+                )
+            })
+    }
+}
+
+/// A walk through an extent tree that leaves reading the index blocks to the caller
+///
+/// Each block returned by `next_block` must be passed to `load` before asking
+/// for the next one, until the walk runs out of blocks.
+#[cfg(feature = "std")]
+pub(crate) struct ExtentTreeWalk {
+    extents: Vec<Extent>,
+    pending: Vec<(u64, u16)>,
+    loading: Option<u16>,
+    loaded: u64,
+    max_blocks: u64,
+}
+
+#[cfg(feature = "std")]
+impl ExtentTreeWalk {
+    /// Deepest tree the kernel accepts
+    pub const MAX_DEPTH: u16 = 5;
+
+    /// Start a walk from the tree root stored in the inode
+    pub fn new(root: &[u32; 15], block_size: u32) -> Result<Self> {
+        // Enough blocks for single-block extents covering every logical
+        // block, with as many index blocks again as headroom
+        let entries_per_block = (block_size as usize - ExtentHeader::SIZE) / Extent::SIZE;
+        let leaves = (Extent::EXT_MAX_BLOCKS as u64 + 1).div_ceil(entries_per_block as u64);
+
+        let root: Vec<u8> = root.iter().flat_map(|&word| word.to_le_bytes()).collect();
+        let mut walk = Self {
+            extents: Vec::new(),
+            pending: Vec::new(),
+            loading: None,
+            loaded: 0,
+            max_blocks: leaves * 2,
+        };
+        walk.parse_node(&root, None)?;
+        Ok(walk)
+    }
+
+    /// Get the next index or leaf block that must be loaded
+    pub fn next_block(&mut self) -> Option<u64> {
+        let (block_num, depth) = self.pending.pop()?;
+        self.loading = Some(depth);
+        Some(block_num)
+    }
+
+    /// Parse the tree node read from the block last returned by `next_block`
+    pub fn load(&mut self, node: &[u8]) -> Result<()> {
+        let expected_depth = self.loading.take();
+        self.loaded += 1;
+        if self.loaded > self.max_blocks {
+            return Err(Error::invalid_data(
+                ParseContext::ExtentHeader,
+                format!("extent tree has more than {} blocks", self.max_blocks),
+            ));
+        }
+        self.parse_node(node, expected_depth)
+    }
+
+    /// Parse a tree node, queueing the blocks it points to
+    ///
+    /// `expected_depth` is `None` for the root, whose depth is only bounded.
+    fn parse_node(&mut self, node: &[u8], expected_depth: Option<u16>) -> Result<()> {
+        let header = ExtentHeader::parse(node.get(..ExtentHeader::SIZE).unwrap_or(node))?;
+        match expected_depth {
+            Some(expected) if header.depth() != expected => {
+                return Err(Error::invalid_data(
+                    ParseContext::ExtentHeader,
+                    format!(
+                        "extent tree node has depth {}, expected {}",
+                        header.depth(),
+                        expected
+                    ),
+                ));
+            }
+            None if header.depth() > Self::MAX_DEPTH => {
+                return Err(Error::invalid_data(
+                    ParseContext::ExtentHeader,
+                    format!(
+                        "extent tree depth {} exceeds the maximum of {}",
+                        header.depth(),
+                        Self::MAX_DEPTH
+                    ),
+                ));
+            }
+            _ => {}
+        }
+
+        let entries = node
+            .get(ExtentHeader::SIZE..)
+            .unwrap_or_default()
+            .chunks_exact(Extent::SIZE)
+            .take(header.entries_count() as usize);
+
+        for entry in entries {
+            if header.depth() == 0 {
+                self.extents.push(Extent::parse(entry)?);
+            } else {
+                let child = ExtentIndex::parse(entry)?.leaf_block();
+                self.pending.push((child, header.depth() - 1));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the map of every leaf extent found
+    pub fn finish(self) -> ExtentMap {
+        ExtentMap::new(self.extents)
+    }
 }
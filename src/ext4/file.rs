@@ -1,24 +1,27 @@
 use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use crate::{Error, Result, Volume, ext4::InodeReader, ext4::inode::Inode};
+use crate::{
+    Error, Result, Volume,
+    ext4::{InodeReader, device::BlockDevice, extent::ExtentMap, inode::Inode, metadata::Metadata},
+};
 
 /// Represents a file in the ext4 filesystem
-pub struct File<R: Read + Seek> {
-    reader: InodeReader<R>,
+pub struct File<D: BlockDevice> {
+    reader: InodeReader<D>,
     inode: Inode,
+    metadata: Metadata,
+    extents: OnceLock<ExtentMap>,
+    data_ranges: OnceLock<Vec<(u64, u64, u64)>>,
     position: u64,
     path: PathBuf,
 }
 
-impl<R: Read + Seek> File<R> {
+impl<D: BlockDevice> File<D> {
     /// Create a new File from a volume, inode, and path
     /// Accepts regular files and symlinks
-    pub(crate) fn new<F: Fn() -> R>(
-        volume: &Volume<R, F>,
-        inode: Inode,
-        path: impl Into<PathBuf>,
-    ) -> Result<Self> {
+    pub(crate) fn new(volume: &Volume<D>, inode: Inode, path: impl Into<PathBuf>) -> Result<Self> {
         let path = path.into();
         if !inode.is_regular_file() && !inode.is_symlink() {
             return Err(Error::NotAFile(format!("{}", path.display())));
@@ -26,7 +29,10 @@ impl<R: Read + Seek> File<R> {
 
         Ok(Self {
             reader: InodeReader::new(volume),
+            metadata: Metadata::new(&inode, volume.superblock()),
             inode,
+            extents: OnceLock::new(),
+            data_ranges: OnceLock::new(),
             position: 0,
             path,
         })
@@ -47,6 +53,11 @@ impl<R: Read + Seek> File<R> {
         &self.inode
     }
 
+    /// Get the metadata of this file
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
     /// Check if this file is a symlink
     pub fn is_symlink(&self) -> bool {
         self.inode().is_symlink()
@@ -57,35 +68,209 @@ impl<R: Read + Seek> File<R> {
         &self.path
     }
 
+    /// Get the extent map, built on first use so the extent tree is walked only once
+    pub(crate) fn extent_map(&self) -> Result<&ExtentMap> {
+        if let Some(extents) = self.extents.get() {
+            return Ok(extents);
+        }
+        let extents = self.reader.extent_map(&self.inode)?;
+        Ok(self.extents.get_or_init(|| extents))
+    }
+
+    /// Get the data stored on disk as `(logical, physical, length)` byte ranges, mapped on first use
+    pub(crate) fn data_ranges(&self) -> Result<&[(u64, u64, u64)]> {
+        if let Some(ranges) = self.data_ranges.get() {
+            return Ok(ranges);
+        }
+        let ranges = self.reader.data_ranges(&self.inode)?;
+        Ok(self.data_ranges.get_or_init(|| ranges))
+    }
+
+    /// Find the first offset at or after `offset` that holds data, like `SEEK_DATA`
+    ///
+    /// Returns `None` when only holes follow. Holes are gaps between extents,
+    /// unwritten extents and zero entries in block maps.
+    pub fn next_data(&self, offset: u64) -> Result<Option<u64>> {
+        if offset >= self.size() {
+            return Ok(None);
+        }
+        let ranges = self.data_ranges()?;
+        let index = ranges.partition_point(|&(logical, _, length)| logical + length <= offset);
+        Ok(ranges
+            .get(index)
+            .map(|&(logical, _, _)| std::cmp::max(logical, offset)))
+    }
+
+    /// Find the first offset at or after `offset` that is in a hole, like `SEEK_HOLE`
+    ///
+    /// The end of the file counts as a hole. Returns `None` past the end.
+    pub fn next_hole(&self, offset: u64) -> Result<Option<u64>> {
+        if offset >= self.size() {
+            return Ok(None);
+        }
+        let ranges = self.data_ranges()?;
+        let index = ranges.partition_point(|&(logical, _, length)| logical + length <= offset);
+        let mut position = offset;
+        for &(logical, _, length) in &ranges[index..] {
+            if logical > position {
+                break;
+            }
+            position = logical + length;
+        }
+        Ok(Some(std::cmp::min(position, self.size())))
+    }
+
+    /// Read the target of this file if it is a symlink
+    pub fn read_link(&self) -> Result<PathBuf> {
+        if !self.is_symlink() {
+            return Err(Error::NotASymlink(format!("{}", self.path.display())));
+        }
+        self.reader.symlink_target(&self.inode)
+    }
+
     /// Read all contents of the file
     pub fn read_all(&mut self) -> Result<Vec<u8>> {
         self.position = 0;
-        self.reader.read_all(&self.inode)
+        let mut data = vec![0u8; self.size() as usize];
+        self.read_at(&mut data, 0)?;
+        Ok(data)
+    }
+
+    /// Read bytes starting at `offset` without moving the file position
+    ///
+    /// Returns the number of bytes read, which is only less than `buf.len()`
+    /// at the end of the file. Takes `&self`, so several threads can read
+    /// different ranges of the same file at once.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let extents = self.extent_map()?;
+        self.reader
+            .read_into_mapped(&self.inode, Some(extents), offset, buf)
+    }
+
+    /// Read exactly `buf.len()` bytes starting at `offset` without moving the file position
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let file_size = self.size();
+        if offset.saturating_add(buf.len() as u64) > file_size {
+            return Err(Error::ReadBeyondEof { file_size, offset });
+        }
+        self.read_at(buf, offset).map(|_| ())
     }
 }
 
-impl<R: Read + Seek> Read for File<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.position >= self.size() {
-            return Ok(0); // EOF
+#[cfg(all(feature = "kernel-copy", target_os = "linux"))]
+impl<D: BlockDevice + std::os::fd::AsFd> File<D> {
+    /// Copy the file into `output`, letting the kernel move the data when it can
+    ///
+    /// Data is reflinked when the image and the output share a filesystem that
+    /// supports it (btrfs, XFS), copied with `copy_file_range` otherwise, and
+    /// through a buffer as a last resort. Returns the most expensive method
+    /// that had to be used.
+    ///
+    /// Reflinks need the image blocks to line up with the host filesystem
+    /// blocks, so images with 1 KiB blocks always fall back on 4 KiB hosts.
+    pub fn copy_to_file(&self, output: &std::fs::File) -> Result<crate::ext4::CopyMethod> {
+        let mut copier = crate::ext4::copy::RangeCopier::new(
+            self.reader.device(),
+            output,
+            self.reader.block_size(),
+        );
+        output.set_len(0)?;
+        for &(logical, physical, length) in self.data_ranges()? {
+            copier.copy(physical, logical, length)?;
         }
+        // Holes are left sparse and reflinked blocks may reach past the end
+        output.set_len(self.size())?;
+        Ok(copier.slowest())
+    }
+}
 
-        let to_read = std::cmp::min(buf.len(), (self.size() - self.position) as usize);
+#[cfg(feature = "parallel")]
+impl<D: BlockDevice + Send + Sync> File<D> {
+    /// Default size of the chunks read concurrently by `par_read_chunks`
+    pub const PARALLEL_CHUNK_SIZE: usize = 8 * 1024 * 1024;
 
-        let data = self
-            .reader
-            .read_data(&self.inode, self.position, to_read)
-            .map_err(std::io::Error::other)?;
+    /// Read the file data in chunks of at most `chunk_size` bytes, concurrently on the rayon pool
+    ///
+    /// `write` is called with the offset within the file and the data found
+    /// there, in no particular order. Holes and unwritten extents are skipped,
+    /// so they read as whatever the output already holds.
+    pub fn par_read_chunks(
+        &self,
+        chunk_size: usize,
+        write: impl Fn(u64, &[u8]) -> Result<()> + Sync,
+    ) -> Result<()> {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        let device = self.reader.device();
+        self.data_chunks(chunk_size as u64)?
+            .into_par_iter()
+            .try_for_each_init(Vec::new, |buffer, (logical, physical, length)| {
+                buffer.resize(length as usize, 0);
+                device.read_at(physical, buffer)?;
+                write(logical, buffer)
+            })
+    }
+
+    /// Split the data stored on disk into `(logical, physical, length)` byte ranges of at most `chunk_size`
+    fn data_chunks(&self, chunk_size: u64) -> Result<Vec<(u64, u64, u64)>> {
+        let mut chunks = Vec::new();
+        for &(logical, physical, length) in self.data_ranges()? {
+            let mut done = 0;
+            while done < length {
+                let chunk = std::cmp::min(length - done, chunk_size.max(1));
+                chunks.push((logical + done, physical + done, chunk));
+                done += chunk;
+            }
+        }
+        Ok(chunks)
+    }
+
+    /// Copy the file into `output` with positional writes, reading chunks concurrently
+    ///
+    /// `output` is resized to the file size first, so holes end up as zeros.
+    pub fn par_copy_to(&self, output: &std::fs::File, chunk_size: usize) -> Result<()> {
+        output.set_len(self.size())?;
+        self.par_read_chunks(chunk_size, |offset, data| {
+            Ok(write_all_at(output, data, offset)?)
+        })
+    }
+}
 
-        let bytes_read = data.len();
-        buf[..bytes_read].copy_from_slice(&data);
+#[cfg(all(feature = "parallel", unix))]
+fn write_all_at(output: &std::fs::File, data: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    output.write_all_at(data, offset)
+}
+
+#[cfg(all(feature = "parallel", windows))]
+fn write_all_at(output: &std::fs::File, mut data: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match output.seek_write(data, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                data = &data[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl<D: BlockDevice> Read for File<D> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self
+            .read_at(buf, self.position)
+            .map_err(std::io::Error::other)?;
         self.position += bytes_read as u64;
 
         Ok(bytes_read)
     }
 }
 
-impl<R: Read + Seek> Seek for File<R> {
+impl<D: BlockDevice> Seek for File<D> {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
         let new_pos = match pos {
             SeekFrom::Start(offset) => offset as i64,
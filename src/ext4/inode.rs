@@ -1,16 +1,26 @@
+use alloc::{format, string::String, vec::Vec};
+
 use bitflags::bitflags;
-use nom::Finish;
-use nom_derive::{NomLE, Parse};
+use nom::{
+    Finish, IResult,
+    number::complete::{le_u16, le_u32},
+};
 
+#[cfg(feature = "std")]
+use crate::ext4::ADDR_SIZE;
 use crate::{
     Error, ParseContext, Result,
-    ext4::xattr::{XAttrEntry, XAttrIbodyHeader},
+    ext4::{
+        le_u32_array,
+        metadata::Timestamp,
+        superblock::{ReadOnlyCompatibleFeatures, Superblock},
+        xattr::{XAttrEntry, XAttrIbodyHeader},
+    },
 };
 
 #[repr(C)]
-#[derive(Debug, Clone, NomLE)]
+#[derive(Debug, Clone)]
 pub struct Inode {
-    #[nom(Parse = "Mode::parse")]
     mode: Mode,
     uid: u16,
     size: u32,
@@ -22,7 +32,6 @@ pub struct Inode {
     links_count: u16,
     blocks: u32,
 
-    #[nom(Parse = "Flags::parse")]
     flags: Flags,
 
     osd1: u32,
@@ -45,7 +54,6 @@ pub struct Inode {
     version_hi: u32,
     project_id: u32,
 
-    #[nom(Ignore)]
     inline_xattrs: Vec<XAttrEntry>,
 }
 
@@ -62,6 +70,15 @@ impl Inode {
 
     // Other constants
     const _BLOCK_SIZE: usize = 512;
+    const SECTOR_SIZE: u64 = 512;
+    const PROJECT_ID_EXTRA_ISIZE: u16 = 32;
+
+    // Minimum extra_isize needed for each extended field to be present
+    const CTIME_EXTRA_ISIZE: u16 = 8;
+    const MTIME_EXTRA_ISIZE: u16 = 12;
+    const ATIME_EXTRA_ISIZE: u16 = 16;
+    const CRTIME_EXTRA_ISIZE: u16 = 20;
+    const CRTIME_EXTRA_EXTRA_ISIZE: u16 = 24;
     pub const GOOD_OLD_SIZE: u16 = 128;
     pub const DIRECT_BLOCKS: u32 = 12;
     pub const INDIRECT_BLOCK_IDX: usize = 12;
@@ -70,7 +87,7 @@ impl Inode {
     pub const FAST_SYMLINK_MAX_SIZE: u64 = 60;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        let mut inode: Inode = match Parse::parse(bytes).finish() {
+        let mut inode: Inode = match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => descriptor,
             Err(e) => return Err(Error::nom_parse(ParseContext::Inode, e)),
         };
@@ -95,14 +112,77 @@ impl Inode {
         Ok(inode)
     }
 
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, mode) = Mode::parse(input)?;
+        let (input, uid) = le_u16(input)?;
+        let (input, size) = le_u32(input)?;
+        let (input, atime) = le_u32(input)?;
+        let (input, ctime) = le_u32(input)?;
+        let (input, mtime) = le_u32(input)?;
+        let (input, dtime) = le_u32(input)?;
+        let (input, gid) = le_u16(input)?;
+        let (input, links_count) = le_u16(input)?;
+        let (input, blocks) = le_u32(input)?;
+        let (input, flags) = Flags::parse(input)?;
+        let (input, osd1) = le_u32(input)?;
+        let (input, block) = le_u32_array(input)?;
+        let (input, generation) = le_u32(input)?;
+        let (input, file_acl) = le_u32(input)?;
+        let (input, size_hi) = le_u32(input)?;
+        let (input, faddr) = le_u32(input)?;
+        let (input, osd2) = Linux2::parse_le(input)?;
+        let (input, extra_isize) = le_u16(input)?;
+        let (input, checksum_hi) = le_u16(input)?;
+        let (input, ctime_extra) = le_u32(input)?;
+        let (input, mtime_extra) = le_u32(input)?;
+        let (input, atime_extra) = le_u32(input)?;
+        let (input, crtime) = le_u32(input)?;
+        let (input, crtime_extra) = le_u32(input)?;
+        let (input, version_hi) = le_u32(input)?;
+        let (input, project_id) = le_u32(input)?;
+        Ok((
+            input,
+            Self {
+                mode,
+                uid,
+                size,
+                atime,
+                ctime,
+                mtime,
+                dtime,
+                gid,
+                links_count,
+                blocks,
+                flags,
+                osd1,
+                block,
+                generation,
+                file_acl,
+                size_hi,
+                faddr,
+                osd2,
+                extra_isize,
+                checksum_hi,
+                ctime_extra,
+                mtime_extra,
+                atime_extra,
+                crtime,
+                crtime_extra,
+                version_hi,
+                project_id,
+                inline_xattrs: Vec::new(),
+            },
+        ))
+    }
+
     /// Parse xattrs from inline inode data
     fn parse_inline_xattr(inline_data: &[u8]) -> Result<Vec<XAttrEntry>> {
-        if inline_data.len() < XAttrIbodyHeader::SIZE {
+        // Like the kernel, a header without the magic (zeroed in quota or journal
+        // inodes, or left over from older kernels) means there are no in-inode xattrs
+        if XAttrIbodyHeader::parse(inline_data).is_err() {
             return Ok(Vec::new());
         }
 
-        XAttrIbodyHeader::parse(inline_data)?; // Validate magic
-
         // Entries start after ibody header (offset 4)
         // e_value_offs is relative to first entry (offset 4)
         XAttrEntry::parse(
@@ -120,6 +200,10 @@ impl Inode {
         self.mode
     }
 
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
     pub fn uid(&self) -> u32 {
         ((self.osd2.uid_high as u32) << 16) | (self.uid as u32)
     }
@@ -128,6 +212,83 @@ impl Inode {
         ((self.osd2.gid_high as u32) << 16) | (self.gid as u32)
     }
 
+    pub fn links_count(&self) -> u16 {
+        self.links_count
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Get the last access time
+    pub fn atime(&self) -> Timestamp {
+        Timestamp::decode(
+            self.atime,
+            self.extra(Self::ATIME_EXTRA_ISIZE, self.atime_extra),
+        )
+    }
+
+    /// Get the last inode change time
+    pub fn ctime(&self) -> Timestamp {
+        Timestamp::decode(
+            self.ctime,
+            self.extra(Self::CTIME_EXTRA_ISIZE, self.ctime_extra),
+        )
+    }
+
+    /// Get the last data modification time
+    pub fn mtime(&self) -> Timestamp {
+        Timestamp::decode(
+            self.mtime,
+            self.extra(Self::MTIME_EXTRA_ISIZE, self.mtime_extra),
+        )
+    }
+
+    /// Get the creation time, if the inode is large enough to record it
+    pub fn crtime(&self) -> Option<Timestamp> {
+        self.extra(Self::CRTIME_EXTRA_ISIZE, self.crtime)?;
+        Some(Timestamp::decode(
+            self.crtime,
+            self.extra(Self::CRTIME_EXTRA_EXTRA_ISIZE, self.crtime_extra),
+        ))
+    }
+
+    /// Get a field from the extended inode area if extra_isize covers it
+    fn extra(&self, required_isize: u16, value: u32) -> Option<u32> {
+        (self.extra_isize >= required_isize).then_some(value)
+    }
+
+    /// Get the deletion time, which holds the next inode number for inodes on the orphan list
+    pub fn dtime(&self) -> u32 {
+        self.dtime
+    }
+
+    /// Get the project ID, or 0 if the inode is too small to carry one
+    pub fn project_id(&self) -> u32 {
+        if self.extra_isize >= Self::PROJECT_ID_EXTRA_ISIZE {
+            self.project_id
+        } else {
+            0
+        }
+    }
+
+    /// Get the number of bytes allocated to this inode on disk
+    pub fn allocated_bytes(&self, superblock: &Superblock) -> u64 {
+        let huge_file = superblock
+            .features_read_only()
+            .contains(ReadOnlyCompatibleFeatures::HugeFile);
+        if !huge_file {
+            return self.blocks as u64 * Self::SECTOR_SIZE;
+        }
+
+        let blocks = ((self.osd2.blocks_high as u64) << 32) | self.blocks as u64;
+        if self.flags.contains(Flags::HugeFile) {
+            blocks * superblock.block_size() as u64
+        } else {
+            blocks * Self::SECTOR_SIZE
+        }
+    }
+
     /// Check if this is a directory
     pub fn is_directory(&self) -> bool {
         matches!(self.mode.file_type(), Some(FileType::Directory))
@@ -153,6 +314,62 @@ impl Inode {
         self.flags.contains(Flags::Extents)
     }
 
+    /// Copy data stored inline in the block array, as fast symlinks do
+    #[cfg(feature = "std")]
+    pub(crate) fn read_inline(&self, offset: u64, buf: &mut [u8]) {
+        let inline_data = self
+            .block
+            .iter()
+            .flat_map(|&word| word.to_le_bytes())
+            .skip(offset as usize);
+
+        for (dest, byte) in buf.iter_mut().zip(inline_data) {
+            *dest = byte;
+        }
+    }
+
+    /// Find where the block map stores the physical block of a logical block
+    #[cfg(feature = "std")]
+    pub(crate) fn block_map_path(&self, logical_block: u32, block_size: u32) -> BlockMapPath {
+        let addr_per_block = block_size / ADDR_SIZE;
+        let path = |root: usize, indices: &[u32]| {
+            let mut path = BlockMapPath {
+                block: self.block[root] as u64,
+                indices: [0; 3],
+                depth: indices.len(),
+            };
+            path.indices[..indices.len()].copy_from_slice(indices);
+            path
+        };
+
+        if logical_block < Self::DIRECT_BLOCKS {
+            return path(logical_block as usize, &[]);
+        }
+
+        let mut block_idx = logical_block - Self::DIRECT_BLOCKS;
+        if block_idx < addr_per_block {
+            return path(Self::INDIRECT_BLOCK_IDX, &[block_idx]);
+        }
+
+        block_idx -= addr_per_block;
+        if block_idx < addr_per_block * addr_per_block {
+            return path(
+                Self::DOUBLE_INDIRECT_BLOCK_IDX,
+                &[block_idx / addr_per_block, block_idx % addr_per_block],
+            );
+        }
+
+        block_idx -= addr_per_block * addr_per_block;
+        path(
+            Self::TRIPLE_INDIRECT_BLOCK_IDX,
+            &[
+                block_idx / (addr_per_block * addr_per_block),
+                (block_idx / addr_per_block) % addr_per_block,
+                block_idx % addr_per_block,
+            ],
+        )
+    }
+
     /// Get only the permission bits from the mode
     pub fn permissions(&self) -> Mode {
         Mode::from_bits_truncate(self.mode.bits() & Self::MODE_PERM_MASK)
@@ -172,6 +389,30 @@ impl Inode {
     }
 }
 
+/// The chain of block map lookups leading to a physical block
+///
+/// Start from `block` and replace it with the address found at each index in
+/// turn. A zero block anywhere along the way is a hole.
+#[cfg(feature = "std")]
+pub(crate) struct BlockMapPath {
+    block: u64,
+    indices: [u32; 3],
+    depth: usize,
+}
+
+#[cfg(feature = "std")]
+impl BlockMapPath {
+    /// Get the block stored in the inode, the answer itself for direct blocks
+    pub fn block(&self) -> u64 {
+        self.block
+    }
+
+    /// Get the index into each indirect block to follow
+    pub fn indices(&self) -> &[u32] {
+        &self.indices[..self.depth]
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum FileType {
@@ -279,11 +520,14 @@ bitflags! {
         const VerityProtected = 0x00100000;
         const ExtendedAttribute = 0x00200000;
         const ExtentsOverflow = 0x00400000;
+        const NoCopyOnWrite = 0x00800000;
         const Snapshot = 0x01000000;
+        const DirectAccess = 0x02000000;
         const SnapshotDeleted = 0x04000000;
         const SnapshotShrunk = 0x08000000;
         const InlineData = 0x10000000;
         const ProjectInherit = 0x20000000;
+        const Casefold = 0x40000000;
         const Reserved = 0x80000000;
     }
 }
@@ -297,6 +541,40 @@ impl Flags {
         Ok((input, Self::from_bits_truncate(bits)))
     }
 
+    /// Flag letters in the order printed by `lsattr`
+    const LSATTR_LETTERS: [(Flags, char); 22] = [
+        (Flags::SecureDelete, 's'),
+        (Flags::Undelete, 'u'),
+        (Flags::Synchronous, 'S'),
+        (Flags::DirectorySync, 'D'),
+        (Flags::Immutable, 'i'),
+        (Flags::AppendOnly, 'a'),
+        (Flags::NoDump, 'd'),
+        (Flags::NoAccessTime, 'A'),
+        (Flags::Compressed, 'c'),
+        (Flags::Encrypted, 'E'),
+        (Flags::JournalData, 'j'),
+        (Flags::HashedIndex, 'I'),
+        (Flags::NoTailMerge, 't'),
+        (Flags::TopDirectory, 'T'),
+        (Flags::Extents, 'e'),
+        (Flags::NoCopyOnWrite, 'C'),
+        (Flags::DirectAccess, 'x'),
+        (Flags::Casefold, 'F'),
+        (Flags::InlineData, 'N'),
+        (Flags::ProjectInherit, 'P'),
+        (Flags::VerityProtected, 'V'),
+        (Flags::NoCompress, 'm'),
+    ];
+
+    /// Format the flags the way `lsattr` does (e.g. "--------------e-------")
+    pub fn lsattr_string(&self) -> String {
+        Self::LSATTR_LETTERS
+            .iter()
+            .map(|&(flag, letter)| if self.contains(flag) { letter } else { '-' })
+            .collect()
+    }
+
     /// Returns only the user-visible flags
     pub fn user_visible(&self) -> Self {
         Self::from_bits_truncate(self.bits() & Self::USER_VISIBLE)
@@ -309,7 +587,7 @@ impl Flags {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, NomLE)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Linux2 {
     pub blocks_high: u16,
     pub file_acl_high: u16,
@@ -318,3 +596,25 @@ pub struct Linux2 {
     pub checksum_lo: u16,
     pub reserved: u16,
 }
+
+impl Linux2 {
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, blocks_high) = le_u16(input)?;
+        let (input, file_acl_high) = le_u16(input)?;
+        let (input, uid_high) = le_u16(input)?;
+        let (input, gid_high) = le_u16(input)?;
+        let (input, checksum_lo) = le_u16(input)?;
+        let (input, reserved) = le_u16(input)?;
+        Ok((
+            input,
+            Self {
+                blocks_high,
+                file_acl_high,
+                uid_high,
+                gid_high,
+                checksum_lo,
+                reserved,
+            },
+        ))
+    }
+}
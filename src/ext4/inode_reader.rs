@@ -1,36 +1,66 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::{
     Volume,
     ext4::{
         ADDR_SIZE, Error, Result,
-        extent::{Extent, ExtentHeader, ExtentIndex},
+        cache::{BlockCache, BlockData},
+        device::BlockDevice,
+        extent::{ExtentMap, ExtentTreeWalk},
         inode::Inode,
         xattr::{self, XAttrEntry},
     },
+    utils::os_str_from_bytes,
 };
 
 /// Low-level reader for inode data
-pub(crate) struct InodeReader<R: Read + Seek> {
-    reader: R,
+pub(crate) struct InodeReader<D: BlockDevice> {
+    device: Arc<D>,
     block_size: u32,
+    cache: Option<Arc<BlockCache>>,
 }
 
-impl<R: Read + Seek> InodeReader<R> {
-    pub fn new<F: Fn() -> R>(volume: &Volume<R, F>) -> Self {
+impl<D: BlockDevice> InodeReader<D> {
+    pub fn new(volume: &Volume<D>) -> Self {
         Self {
-            reader: volume.reader(),
+            device: volume.shared_device(),
             block_size: volume.block_size(),
+            cache: volume.block_cache(),
         }
     }
 
+    /// Get the device the inode data is read from
+    #[cfg(any(
+        feature = "parallel",
+        all(feature = "kernel-copy", target_os = "linux")
+    ))]
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Get the block size of the volume
+    #[cfg(all(feature = "kernel-copy", target_os = "linux"))]
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
     /// Read all data from the inode
-    pub fn read_all(&mut self, inode: &Inode) -> Result<Vec<u8>> {
+    pub fn read_all(&self, inode: &Inode) -> Result<Vec<u8>> {
         self.read_data(inode, 0, inode.size() as usize)
     }
 
+    /// Read the target stored in a symlink inode, empty if it has none
+    pub fn symlink_target(&self, inode: &Inode) -> Result<PathBuf> {
+        if inode.size() == 0 {
+            return Ok(PathBuf::new());
+        }
+        let target = self.read_all(inode)?;
+        Ok(PathBuf::from(os_str_from_bytes(&target).into_owned()))
+    }
+
     /// Read data at a given offset
-    pub fn read_data(&mut self, inode: &Inode, offset: u64, length: usize) -> Result<Vec<u8>> {
+    pub fn read_data(&self, inode: &Inode, offset: u64, length: usize) -> Result<Vec<u8>> {
         let file_size = inode.size();
 
         if offset >= file_size {
@@ -39,69 +69,198 @@ impl<R: Read + Seek> InodeReader<R> {
 
         let actual_length = std::cmp::min(length, (file_size - offset) as usize);
         let mut result = vec![0u8; actual_length];
+        self.read_into(inode, offset, &mut result)?;
+
+        Ok(result)
+    }
+
+    /// Read data at a given offset into `buf`, returning the number of bytes read
+    ///
+    /// Fewer bytes than requested are read only at the end of the file.
+    pub fn read_into(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.read_into_mapped(inode, None, offset, buf)
+    }
+
+    /// Like `read_into`, using an extent map built earlier by `extent_map`
+    ///
+    /// Callers reading the same inode many times keep the map to avoid walking
+    /// the extent tree on every read.
+    pub fn read_into_mapped(
+        &self,
+        inode: &Inode,
+        extents: Option<&ExtentMap>,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let file_size = inode.size();
+        if offset >= file_size {
+            return Ok(0);
+        }
+
+        let length = std::cmp::min(buf.len() as u64, file_size - offset) as usize;
+        let buf = &mut buf[..length];
 
         if inode.is_fast_symlink() {
-            self.read_fast_symlink(inode, offset, &mut result);
+            inode.read_inline(offset, buf);
         } else if inode.uses_extents() {
-            self.read_via_extents(inode, offset, &mut result)?;
+            match extents {
+                Some(extents) => self.read_via_extents(inode, extents, offset, buf)?,
+                None => self.read_via_extents(inode, &self.extent_map(inode)?, offset, buf)?,
+            }
         } else {
-            self.read_via_indirect(inode, offset, &mut result)?;
+            self.read_via_indirect(inode, offset, buf)?;
         }
 
-        Ok(result)
+        Ok(length)
     }
 
-    /// Read data from a fast symlink (inline in inode.block)
-    fn read_fast_symlink(&self, inode: &Inode, offset: u64, buf: &mut [u8]) {
-        let inline_data: Vec<u8> = inode
-            .block
-            .iter()
-            .flat_map(|&word| word.to_le_bytes())
-            .collect();
-
-        let start = offset as usize;
-        let end = start + buf.len();
-        buf.copy_from_slice(&inline_data[start..end]);
+    /// Build the extent map of an inode, empty if it does not use extents
+    pub fn extent_map(&self, inode: &Inode) -> Result<ExtentMap> {
+        if !inode.uses_extents() || inode.is_fast_symlink() {
+            return Ok(ExtentMap::default());
+        }
+        let mut walk = ExtentTreeWalk::new(&inode.block, self.block_size)?;
+        while let Some(block_num) = walk.next_block() {
+            walk.load(&self.read_block(block_num)?)?;
+        }
+        Ok(walk.finish())
     }
 
-    fn read_via_extents(&mut self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<()> {
-        let extents = self.parse_extent_tree(&inode.block)?;
-        let mut bytes_read = 0;
+    /// Map the data of an inode stored on disk as `(logical, physical, length)` byte ranges
+    ///
+    /// Holes, unwritten extents and inline data are not included. Ranges are
+    /// clipped to the file size and sorted by logical offset.
+    pub fn data_ranges(&self, inode: &Inode) -> Result<Vec<(u64, u64, u64)>> {
+        let block_size = self.block_size as u64;
+        let file_size = inode.size();
+        let clip = |logical: u64, length: u64| std::cmp::min(length, file_size - logical);
 
-        for extent in extents {
-            let extent_start = extent.first_block() * self.block_size as u64;
-            let extent_len = extent.get_actual_len() as u64 * self.block_size as u64;
-            let extent_end = extent_start + extent_len;
+        if inode.is_fast_symlink() {
+            return Ok(Vec::new());
+        }
 
-            if offset + buf.len() as u64 <= extent_start || offset >= extent_end {
-                continue;
+        if inode.uses_extents() {
+            return Ok(self
+                .extent_map(inode)?
+                .extents()
+                .iter()
+                .filter(|extent| !extent.is_unwritten())
+                .map(|extent| {
+                    let logical = extent.first_block() * block_size;
+                    let length = extent.get_actual_len() as u64 * block_size;
+                    (logical, extent.start_block() * block_size, length)
+                })
+                .filter(|&(logical, _, _)| logical < file_size)
+                .map(|(logical, physical, length)| (logical, physical, clip(logical, length)))
+                .collect());
+        }
+
+        // Block maps are walked one indirect block at a time, merging contiguous blocks
+        let block_count = file_size.div_ceil(block_size);
+        let mut ranges: Vec<(u64, u64, u64)> = Vec::new();
+        let mut push = |block_idx: u64, physical_block: u64| {
+            let logical = block_idx * block_size;
+            let physical = physical_block * block_size;
+            let length = clip(logical, block_size);
+            match ranges.last_mut() {
+                Some((last_logical, last_physical, last_length))
+                    if *last_logical + *last_length == logical
+                        && *last_physical + *last_length == physical =>
+                {
+                    *last_length += length;
+                }
+                _ => ranges.push((logical, physical, length)),
             }
+        };
 
-            let read_start = offset.saturating_sub(extent_start);
-            let read_end = std::cmp::min(extent_len, offset + buf.len() as u64 - extent_start);
-            let to_read = (read_end - read_start) as usize;
+        let direct = &inode.block[..Inode::DIRECT_BLOCKS as usize];
+        for (block_idx, &physical_block) in (0..block_count).zip(direct) {
+            if physical_block != 0 {
+                push(block_idx, physical_block as u64);
+            }
+        }
 
-            let physical_offset = extent.start_block() * self.block_size as u64 + read_start;
+        let addr_per_block = (self.block_size / ADDR_SIZE) as u64;
+        let mut first_block = Inode::DIRECT_BLOCKS as u64;
+        let mut span = 1;
+        for root in [
+            Inode::INDIRECT_BLOCK_IDX,
+            Inode::DOUBLE_INDIRECT_BLOCK_IDX,
+            Inode::TRIPLE_INDIRECT_BLOCK_IDX,
+        ] {
+            span *= addr_per_block;
+            self.walk_block_map(
+                inode.block[root] as u64,
+                first_block,
+                span,
+                block_count,
+                &mut push,
+            )?;
+            first_block += span;
+        }
 
-            self.reader.seek(SeekFrom::Start(physical_offset))?;
-            self.reader
-                .read_exact(&mut buf[bytes_read..bytes_read + to_read])?;
+        Ok(ranges)
+    }
 
-            bytes_read += to_read;
-            if bytes_read >= buf.len() {
+    /// Report the data blocks below an indirect block, reading each indirect block once
+    ///
+    /// `first_block` is the first logical block the indirect block maps and
+    /// `span` the number of logical blocks it covers. Blocks at or past
+    /// `block_count` are skipped.
+    fn walk_block_map(
+        &self,
+        block_num: u64,
+        first_block: u64,
+        span: u64,
+        block_count: u64,
+        push: &mut impl FnMut(u64, u64),
+    ) -> Result<()> {
+        if block_num == 0 || first_block >= block_count {
+            return Ok(());
+        }
+
+        let child_span = span / (self.block_size / ADDR_SIZE) as u64;
+        let block_data = self.read_block(block_num)?;
+        for (index, raw) in block_data.chunks_exact(ADDR_SIZE as usize).enumerate() {
+            let child_first = first_block + index as u64 * child_span;
+            if child_first >= block_count {
                 break;
             }
+            let child = u32::from_le_bytes(raw.try_into().unwrap()) as u64;
+            if child_span == 1 {
+                if child != 0 {
+                    push(child_first, child);
+                }
+            } else {
+                self.walk_block_map(child, child_first, child_span, block_count, push)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_via_extents(
+        &self,
+        inode: &Inode,
+        extents: &ExtentMap,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        // Holes and unwritten extents read as zeros
+        buf.fill(0);
+
+        for (dest, physical_offset) in extents.segments(offset, buf.len(), self.block_size) {
+            self.read_physical(inode, physical_offset, &mut buf[dest])?;
         }
 
         Ok(())
     }
 
-    fn read_via_indirect(&mut self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<()> {
+    fn read_via_indirect(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<()> {
         let block_size = self.block_size as u64;
         let start_block = offset / block_size;
         let end_block = (offset + buf.len() as u64).div_ceil(block_size);
         let mut bytes_read = 0;
-        let inode_block = inode.block;
 
         for block_idx in start_block..end_block {
             let block_offset = if block_idx == start_block {
@@ -115,15 +274,17 @@ impl<R: Read + Seek> InodeReader<R> {
                 buf.len() - bytes_read,
             );
 
-            let physical_block = self.resolve_block(&inode_block, block_idx as u32)?;
+            let physical_block = self.resolve_block(inode, block_idx as u32)?;
 
             if physical_block == 0 {
                 buf[bytes_read..bytes_read + to_read].fill(0);
             } else {
                 let physical_offset = physical_block * block_size + block_offset as u64;
-                self.reader.seek(SeekFrom::Start(physical_offset))?;
-                self.reader
-                    .read_exact(&mut buf[bytes_read..bytes_read + to_read])?;
+                self.read_physical(
+                    inode,
+                    physical_offset,
+                    &mut buf[bytes_read..bytes_read + to_read],
+                )?;
             }
 
             bytes_read += to_read;
@@ -132,18 +293,41 @@ impl<R: Read + Seek> InodeReader<R> {
         Ok(())
     }
 
-    fn read_block(&mut self, block_num: u64) -> Result<Vec<u8>> {
-        let offset = block_num * self.block_size as u64;
-        self.reader.seek(SeekFrom::Start(offset))?;
+    /// Read data of an inode from the device, through the block cache for directories
+    fn read_physical(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let Some(cache) = self.cache.as_ref().filter(|_| inode.is_directory()) else {
+            return self.device.read_at(offset, buf);
+        };
 
-        let mut buffer = vec![0u8; self.block_size as usize];
-        self.reader.read_exact(&mut buffer)?;
+        let block_size = self.block_size as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let block = cache.block(&*self.device, position / block_size, self.block_size)?;
+            let start = (position % block_size) as usize;
+            let length = std::cmp::min(block.len() - start, buf.len() - done);
+            buf[done..done + length].copy_from_slice(&block[start..start + length]);
+            done += length;
+        }
 
-        Ok(buffer)
+        Ok(())
+    }
+
+    fn read_block(&self, block_num: u64) -> Result<BlockData<'_>> {
+        if let Some(cache) = &self.cache {
+            return cache
+                .block(&*self.device, block_num, self.block_size)
+                .map(BlockData::Cached);
+        }
+
+        let offset = block_num * self.block_size as u64;
+        self.device
+            .read_bytes(offset, self.block_size as usize)
+            .map(BlockData::Device)
     }
 
     /// Read extended attributes for an inode
-    pub fn read_xattrs(&mut self, inode: &Inode) -> Result<Vec<XAttrEntry>> {
+    pub fn read_xattrs(&self, inode: &Inode) -> Result<Vec<XAttrEntry>> {
         let mut xattrs = Vec::new();
 
         // Add inline xattrs (already parsed during Inode::parse)
@@ -160,7 +344,7 @@ impl<R: Read + Seek> InodeReader<R> {
         Ok(xattrs)
     }
 
-    fn read_block_addr(&mut self, block_num: u64, index: u32) -> Result<u64> {
+    fn read_block_addr(&self, block_num: u64, index: u32) -> Result<u64> {
         if block_num == 0 {
             return Ok(0);
         }
@@ -169,65 +353,12 @@ impl<R: Read + Seek> InodeReader<R> {
         Ok(u32::from_le_bytes(block_data[offset..offset + 4].try_into().unwrap()) as u64)
     }
 
-    fn resolve_block(&mut self, inode_block: &[u32; 15], logical_block: u32) -> Result<u64> {
-        let addr_per_block = self.block_size / 4;
-
-        if logical_block < Inode::DIRECT_BLOCKS {
-            return Ok(inode_block[logical_block as usize] as u64);
-        }
-
-        let mut block_idx = logical_block - Inode::DIRECT_BLOCKS;
-
-        if block_idx < addr_per_block {
-            return self.read_block_addr(inode_block[Inode::INDIRECT_BLOCK_IDX] as u64, block_idx);
-        }
-
-        block_idx -= addr_per_block;
-
-        if block_idx < addr_per_block * addr_per_block {
-            let indirect = self.read_block_addr(
-                inode_block[Inode::DOUBLE_INDIRECT_BLOCK_IDX] as u64,
-                block_idx / addr_per_block,
-            )?;
-            return self.read_block_addr(indirect, block_idx % addr_per_block);
-        }
-
-        block_idx -= addr_per_block * addr_per_block;
-
-        let double = self.read_block_addr(
-            inode_block[Inode::TRIPLE_INDIRECT_BLOCK_IDX] as u64,
-            block_idx / (addr_per_block * addr_per_block),
-        )?;
-        let indirect =
-            self.read_block_addr(double, (block_idx / addr_per_block) % addr_per_block)?;
-        self.read_block_addr(indirect, block_idx % addr_per_block)
-    }
-
-    fn parse_extent_tree(&mut self, block_data: &[u32; 15]) -> Result<Vec<Extent>> {
-        let bytes: Vec<u8> = block_data
+    fn resolve_block(&self, inode: &Inode, logical_block: u32) -> Result<u64> {
+        let path = inode.block_map_path(logical_block, self.block_size);
+        path.indices()
             .iter()
-            .flat_map(|&word| word.to_le_bytes())
-            .collect();
-
-        self.parse_extent_tree_from_block(&bytes)
-    }
-
-    fn parse_extent_tree_from_block(&mut self, block_data: &[u8]) -> Result<Vec<Extent>> {
-        let header = ExtentHeader::parse(&block_data[..ExtentHeader::SIZE])?;
-        let mut extents = Vec::new();
-        let mut offset = ExtentHeader::SIZE;
-
-        for _ in 0..header.entries_count() {
-            if header.depth() == 0 {
-                extents.push(Extent::parse(&block_data[offset..offset + Extent::SIZE])?);
-            } else {
-                let index = ExtentIndex::parse(&block_data[offset..offset + ExtentIndex::SIZE])?;
-                let child_block_data = self.read_block(index.leaf_block())?;
-                extents.extend(self.parse_extent_tree_from_block(&child_block_data)?);
-            }
-            offset += Extent::SIZE;
-        }
-
-        Ok(extents)
+            .try_fold(path.block(), |block_num, &index| {
+                self.read_block_addr(block_num, index)
+            })
     }
 }
@@ -0,0 +1,206 @@
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ext4::{
+    inode::{FileType, Flags, Inode, Mode},
+    superblock::Superblock,
+};
+
+/// An inode timestamp with nanosecond precision
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    seconds: i64,
+    nanoseconds: u32,
+}
+
+impl Timestamp {
+    const EPOCH_BITS: u32 = 2;
+    const EPOCH_MASK: u32 = (1 << Self::EPOCH_BITS) - 1;
+
+    /// Decode a timestamp from its base seconds and optional `_extra` field
+    ///
+    /// The low two bits of `extra` extend the signed 32-bit seconds past 2038,
+    /// the remaining 30 bits hold the nanoseconds.
+    pub fn decode(seconds: u32, extra: Option<u32>) -> Self {
+        let mut timestamp = Self {
+            seconds: seconds as i32 as i64,
+            nanoseconds: 0,
+        };
+
+        if let Some(extra) = extra {
+            timestamp.seconds += ((extra & Self::EPOCH_MASK) as i64) << 32;
+            timestamp.nanoseconds = extra >> Self::EPOCH_BITS;
+        }
+
+        timestamp
+    }
+
+    /// Create a timestamp with whole-second precision
+    pub fn from_seconds(seconds: i64) -> Self {
+        Self {
+            seconds,
+            nanoseconds: 0,
+        }
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// Nanoseconds within the second
+    pub fn nanoseconds(&self) -> u32 {
+        self.nanoseconds
+    }
+
+    #[cfg(feature = "std")]
+    pub fn to_system_time(&self) -> SystemTime {
+        let nanos = Duration::from_nanos(self.nanoseconds as u64);
+        if self.seconds >= 0 {
+            UNIX_EPOCH + Duration::from_secs(self.seconds as u64) + nanos
+        } else {
+            UNIX_EPOCH - Duration::from_secs(self.seconds.unsigned_abs()) + nanos
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> Self {
+        timestamp.to_system_time()
+    }
+}
+
+/// Metadata about a file, directory or other inode (like `std::fs::Metadata`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    mode: Mode,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    links_count: u16,
+    generation: u32,
+    flags: Flags,
+    project_id: u32,
+    allocated_bytes: u64,
+    atime: Timestamp,
+    ctime: Timestamp,
+    mtime: Timestamp,
+    crtime: Option<Timestamp>,
+}
+
+impl Metadata {
+    /// Collect the metadata of an inode
+    pub fn new(inode: &Inode, superblock: &Superblock) -> Self {
+        Self {
+            mode: inode.mode(),
+            uid: inode.uid(),
+            gid: inode.gid(),
+            size: inode.size(),
+            links_count: inode.links_count(),
+            generation: inode.generation(),
+            flags: inode.flags(),
+            project_id: inode.project_id(),
+            allocated_bytes: inode.allocated_bytes(superblock),
+            atime: inode.atime(),
+            ctime: inode.ctime(),
+            mtime: inode.mtime(),
+            crtime: inode.crtime(),
+        }
+    }
+
+    pub fn file_type(&self) -> Option<FileType> {
+        self.mode.file_type()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        matches!(self.file_type(), Some(FileType::Directory))
+    }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self.file_type(), Some(FileType::RegularFile))
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        matches!(self.file_type(), Some(FileType::SymbolicLink))
+    }
+
+    /// Get the full mode, including the file type bits
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Get the permission bits (owner/group/other + setuid/setgid/sticky)
+    pub fn permissions(&self) -> u16 {
+        self.mode.permissions()
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Get the file size in bytes
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn links_count(&self) -> u16 {
+        self.links_count
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    pub fn project_id(&self) -> u32 {
+        self.project_id
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Get the inode flags as printed by `lsattr`
+    pub fn lsattr(&self) -> String {
+        self.flags.lsattr_string()
+    }
+
+    /// Get the number of 512-byte sectors allocated on disk (like `st_blocks`)
+    pub fn blocks(&self) -> u64 {
+        self.allocated_bytes / 512
+    }
+
+    /// Get the number of bytes allocated on disk
+    pub fn allocated_bytes(&self) -> u64 {
+        self.allocated_bytes
+    }
+
+    /// Get the last access time
+    pub fn accessed(&self) -> Timestamp {
+        self.atime
+    }
+
+    /// Get the last data modification time
+    pub fn modified(&self) -> Timestamp {
+        self.mtime
+    }
+
+    /// Get the last inode change time
+    pub fn changed(&self) -> Timestamp {
+        self.ctime
+    }
+
+    /// Get the creation time, if recorded
+    pub fn created(&self) -> Option<Timestamp> {
+        self.crtime
+    }
+}
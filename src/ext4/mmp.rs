@@ -0,0 +1,187 @@
+use nom::{
+    Finish, IResult,
+    combinator::verify,
+    number::complete::{le_u16, le_u32, le_u64},
+};
+
+use crate::{
+    Error, ParseContext, Result, Volume,
+    ext4::{
+        byte_array, device::BlockDevice, le_u32_array, metadata::Timestamp,
+        superblock::IncompatibleFeatures,
+    },
+};
+
+/// The multi-mount protection block
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct MmpBlock {
+    magic: u32,
+    sequence: u32,
+    time: u64,
+    node_name: [u8; 64],
+    device_name: [u8; 32],
+    check_interval: u16,
+    pad1: u16,
+    pad2: [u32; 226],
+    checksum: u32,
+}
+
+/// State of the filesystem as recorded by the MMP sequence number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmpStatus {
+    /// The filesystem was cleanly unmounted
+    Clean,
+    /// e2fsck was running on the filesystem
+    Fsck,
+    /// The filesystem was mounted and the sequence was being updated
+    Active(u32),
+    /// The sequence number is outside the valid range
+    Invalid(u32),
+}
+
+/// A warning about the state captured in the MMP block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MmpWarning {
+    /// The image was taken while the filesystem was mounted
+    Mounted { node: String, device: String },
+    /// The image was taken while e2fsck was running
+    BeingChecked { node: String, device: String },
+    /// The MMP sequence number is corrupt
+    InvalidSequence(u32),
+}
+
+impl std::fmt::Display for MmpWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MmpWarning::Mounted { node, device } => write!(
+                f,
+                "filesystem was mounted on node '{}' (device '{}') when the image was taken",
+                node, device
+            ),
+            MmpWarning::BeingChecked { node, device } => write!(
+                f,
+                "filesystem was being checked on node '{}' (device '{}') when the image was taken",
+                node, device
+            ),
+            MmpWarning::InvalidSequence(sequence) => {
+                write!(
+                    f,
+                    "MMP block has an invalid sequence number {:#x}",
+                    sequence
+                )
+            }
+        }
+    }
+}
+
+impl MmpBlock {
+    pub const SIZE: usize = 1024;
+    const SEQUENCE_CLEAN: u32 = 0xFF4D4D50;
+    const SEQUENCE_FSCK: u32 = 0xE24D4D50;
+    const SEQUENCE_MAX: u32 = 0xE24D4D4F;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        match Self::parse_le(bytes).finish() {
+            Ok((_, block)) => Ok(block),
+            Err(e) => Err(Error::nom_parse(ParseContext::Mmp, e)),
+        }
+    }
+
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, magic) = verify(le_u32, |magic| *magic == 0x004D4D50)(input)?;
+        let (input, sequence) = le_u32(input)?;
+        let (input, time) = le_u64(input)?;
+        let (input, node_name) = byte_array(input)?;
+        let (input, device_name) = byte_array(input)?;
+        let (input, check_interval) = le_u16(input)?;
+        let (input, pad1) = le_u16(input)?;
+        let (input, pad2) = le_u32_array(input)?;
+        let (input, checksum) = le_u32(input)?;
+        Ok((
+            input,
+            Self {
+                magic,
+                sequence,
+                time,
+                node_name,
+                device_name,
+                check_interval,
+                pad1,
+                pad2,
+                checksum,
+            },
+        ))
+    }
+
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn status(&self) -> MmpStatus {
+        match self.sequence {
+            Self::SEQUENCE_CLEAN => MmpStatus::Clean,
+            Self::SEQUENCE_FSCK => MmpStatus::Fsck,
+            sequence if sequence <= Self::SEQUENCE_MAX => MmpStatus::Active(sequence),
+            sequence => MmpStatus::Invalid(sequence),
+        }
+    }
+
+    /// Time of the last update
+    pub fn time(&self) -> Timestamp {
+        Timestamp::from_seconds(self.time as i64)
+    }
+
+    /// Name of the node that last updated the block
+    pub fn node_name(&self) -> String {
+        Self::c_string(&self.node_name)
+    }
+
+    /// Name of the block device the filesystem was mounted from
+    pub fn device_name(&self) -> String {
+        Self::c_string(&self.device_name)
+    }
+
+    /// Interval in seconds between sequence updates
+    pub fn check_interval(&self) -> u16 {
+        self.check_interval
+    }
+
+    /// Get a warning if the block shows the filesystem was in use
+    pub fn warning(&self) -> Option<MmpWarning> {
+        match self.status() {
+            MmpStatus::Clean => None,
+            MmpStatus::Fsck => Some(MmpWarning::BeingChecked {
+                node: self.node_name(),
+                device: self.device_name(),
+            }),
+            MmpStatus::Active(_) => Some(MmpWarning::Mounted {
+                node: self.node_name(),
+                device: self.device_name(),
+            }),
+            MmpStatus::Invalid(sequence) => Some(MmpWarning::InvalidSequence(sequence)),
+        }
+    }
+
+    fn c_string(bytes: &[u8]) -> String {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Read the MMP block, if multi-mount protection is enabled
+    pub fn mmp(&self) -> Result<Option<MmpBlock>> {
+        let enabled = self
+            .superblock()
+            .features_incompatible()
+            .contains(IncompatibleFeatures::MultipleMountProtection);
+        let block = self.superblock().mmp_block();
+        if !enabled || block == 0 {
+            return Ok(None);
+        }
+
+        let buffer = self.read_bytes(block * self.block_size() as u64, MmpBlock::SIZE)?;
+        MmpBlock::parse(&buffer).map(Some)
+    }
+}
@@ -1,20 +1,132 @@
+/// Define an enum over a raw on-disk value that keeps unlisted values as `Unknown`
+macro_rules! open_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident: $raw:ty => $parser:ident {
+            $($(#[$variant_meta:meta])* $variant:ident = $value:expr,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+            Unknown($raw),
+        }
+
+        impl $name {
+            pub fn parse(input: &[u8]) -> nom::IResult<&[u8], Self> {
+                let (input, value) = nom::number::complete::$parser(input)?;
+                Ok((input, Self::from(value)))
+            }
+
+            /// Get the raw on-disk value
+            pub fn raw(&self) -> $raw {
+                match self {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(value) => *value,
+                }
+            }
+        }
+
+        impl From<$raw> for $name {
+            fn from(value: $raw) -> Self {
+                match value {
+                    $($value => $name::$variant,)*
+                    _ => $name::Unknown(value),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "async")]
+mod async_volume;
 mod block;
+#[cfg(feature = "std")]
+mod cache;
+mod compat;
+#[cfg(all(feature = "kernel-copy", target_os = "linux"))]
+mod copy;
+#[cfg(feature = "std")]
+mod dentry;
+mod device;
+#[cfg(feature = "std")]
 mod directory;
 mod extent;
+#[cfg(feature = "std")]
 mod file;
 mod inode;
+#[cfg(feature = "std")]
 mod inode_reader;
+mod metadata;
+#[cfg(feature = "std")]
+mod mmp;
+#[cfg(feature = "std")]
+mod node;
+#[cfg(feature = "std")]
+mod orphan;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "std")]
+mod plan;
+#[cfg(feature = "std")]
+mod quota;
+#[cfg(feature = "std")]
+mod resolve;
 mod superblock;
+#[cfg(feature = "std")]
 mod volume;
+#[cfg(feature = "std")]
 mod walker;
 mod xattr;
 
-pub use directory::Directory;
+#[cfg(feature = "async")]
+pub use async_volume::{AsyncBlockDevice, AsyncDirectoryWalker, AsyncFile, AsyncVolume, Blocking};
+pub use block::{BlockGroupDescriptor, Flags as BlockGroupFlags};
+#[cfg(feature = "std")]
+pub use cache::CacheStats;
+pub use compat::{CompatibilityReport, Feature, FeaturePolicy, Impact, UnsupportedFeature};
+#[cfg(all(feature = "kernel-copy", target_os = "linux"))]
+pub use copy::CopyMethod;
+pub use device::BlockDevice;
+#[cfg(feature = "std")]
+pub use device::ReaderFactory;
+#[cfg(feature = "std")]
+pub use directory::{Directory, Entries};
+pub use extent::{Extent, ExtentHeader, ExtentIndex, ExtentMap};
+#[cfg(feature = "std")]
 pub use file::File;
-pub use inode::FileType;
+pub use inode::{FileType, Flags as InodeFlags, Inode, Mode};
+#[cfg(feature = "std")]
 use inode_reader::InodeReader;
+pub use metadata::{Metadata, Timestamp};
+#[cfg(feature = "std")]
+pub use mmp::{MmpBlock, MmpStatus, MmpWarning};
+#[cfg(feature = "std")]
+pub use node::Node;
+#[cfg(feature = "std")]
+pub use orphan::{Orphan, OrphanAction, OrphanPolicy, OrphanSource};
+#[cfg(feature = "parallel")]
+pub use parallel::ParallelWalker;
+#[cfg(feature = "std")]
+pub use plan::{DataRun, ExtractionPlan};
+#[cfg(feature = "std")]
+pub use quota::{QuotaEntry, QuotaFile, QuotaInfo, QuotaMismatch, QuotaType, QuotaUsage};
+#[cfg(feature = "std")]
+pub use resolve::SymlinkPolicy;
+pub use superblock::{
+    CompatibleFeatures, CreatorOS, DefaultHashVersion, EncryptionAlgorithm, ErrorPolicy,
+    IncompatibleFeatures, ReadOnlyCompatibleFeatures, Revision, State, Superblock,
+};
+#[cfg(feature = "std")]
 pub use volume::Volume;
+#[cfg(feature = "std")]
 pub use walker::{DirectoryWalker, EntryAttributes, WalkItem};
+pub use xattr::{EncryptionContext, XAttrEntry, XAttrNameIndex, parse_xattrs_from_block};
+
+use alloc::{borrow::Cow, boxed::Box, string::String};
+#[cfg(feature = "std")]
+use std::ffi::OsStr;
 
 // Re-export errors from utils
 pub use crate::utils::{Error, ParseContext, Result};
@@ -23,6 +135,21 @@ pub type Ext4Lblk = u32;
 pub type Ext4Fsblk = u64;
 pub const ADDR_SIZE: u32 = 4;
 
+/// Parse a fixed-size byte array
+pub(crate) fn byte_array<const N: usize>(input: &[u8]) -> nom::IResult<&[u8], [u8; N]> {
+    let (input, bytes) = nom::bytes::complete::take(N)(input)?;
+    Ok((input, bytes.try_into().unwrap()))
+}
+
+/// Parse a fixed-size array of little-endian 32-bit words
+pub(crate) fn le_u32_array<const N: usize>(mut input: &[u8]) -> nom::IResult<&[u8], [u32; N]> {
+    let mut words = [0; N];
+    for word in &mut words {
+        (input, *word) = nom::number::complete::le_u32(input)?;
+    }
+    Ok((input, words))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum DirEntryType {
@@ -52,22 +179,89 @@ impl From<u8> for DirEntryType {
     }
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct DirectoryEntry {
     pub inode: u32,
     pub entry_len: u16,
-    pub name_len: u8,
     pub inode_type: u8,
-    pub name: [u8; 255],
+    name: Box<[u8]>,
+    block: u64,
+    offset: u32,
 }
 
 impl DirectoryEntry {
     pub const HEADER_SIZE: usize = 8;
     pub const MAX_NAME_LEN: usize = 255;
-    pub fn name_str(&self) -> &str {
-        let len = self.name_len as usize;
-        std::str::from_utf8(&self.name[..len]).unwrap_or("")
+
+    /// Parse the entry at `offset` within a directory block, returning its record length
+    ///
+    /// `block` is the logical block of the directory `data` was read from. The
+    /// entry is `None` for unused records (inode 0), such as deleted entries and
+    /// the checksum tail.
+    pub fn parse(data: &[u8], block: u64, offset: usize) -> Result<(usize, Option<Self>)> {
+        let corrupted = || Error::CorruptedDirectoryEntry(offset);
+        let header = data
+            .get(offset..offset + Self::HEADER_SIZE)
+            .ok_or_else(corrupted)?;
+
+        let inode = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let entry_len = u16::from_le_bytes([header[4], header[5]]);
+        let name_len = header[6] as usize;
+        let inode_type = header[7];
+
+        if (entry_len as usize) < Self::HEADER_SIZE || entry_len as usize > data.len() - offset {
+            return Err(corrupted());
+        }
+        if inode == 0 {
+            return Ok((entry_len as usize, None));
+        }
+
+        let name_start = offset + Self::HEADER_SIZE;
+        let name = data
+            .get(name_start..name_start + name_len)
+            .filter(|_| Self::HEADER_SIZE + name_len <= entry_len as usize)
+            .ok_or_else(corrupted)?;
+
+        let entry = Self {
+            inode,
+            entry_len,
+            inode_type,
+            name: name.into(),
+            block,
+            offset: offset as u32,
+        };
+        Ok((entry_len as usize, Some(entry)))
+    }
+
+    /// Get the raw bytes of the name
+    pub fn name_bytes(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Get the logical block of the directory that holds this entry
+    pub fn block(&self) -> u64 {
+        self.block
+    }
+
+    /// Get the byte offset of this entry within its block
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Get the name as an `OsStr`, lossless on Unix
+    #[cfg(feature = "std")]
+    pub fn name(&self) -> Cow<'_, OsStr> {
+        crate::utils::os_str_from_bytes(self.name_bytes())
+    }
+
+    /// Get the name as a string, replacing invalid UTF-8 sequences
+    pub fn name_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.name_bytes())
+    }
+
+    /// Check if this is the `.` or `..` entry
+    pub fn is_dot_or_dotdot(&self) -> bool {
+        matches!(self.name_bytes(), b"." | b"..")
     }
 
     pub fn entry_type(&self) -> DirEntryType {
@@ -0,0 +1,73 @@
+use crate::{
+    Directory, Error, File, Result, Volume,
+    ext4::{
+        device::BlockDevice,
+        inode::{FileType, Inode},
+    },
+};
+
+/// A handle to an inode opened by number
+///
+/// Handles opened by inode number have no known path, so `path()` is empty.
+pub enum Node<D: BlockDevice> {
+    File(File<D>),
+    /// Boxed, a directory carries a handle to the whole volume
+    Directory(Box<Directory<D>>),
+    Symlink(File<D>),
+    /// Device nodes, FIFOs and sockets, which have no data to read
+    Special(Inode),
+}
+
+impl<D: BlockDevice> Node<D> {
+    /// Get a reference to the inode
+    pub fn inode(&self) -> &Inode {
+        match self {
+            Node::File(file) | Node::Symlink(file) => file.inode(),
+            Node::Directory(directory) => directory.inode(),
+            Node::Special(inode) => inode,
+        }
+    }
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Open an inode by number without resolving any path
+    pub fn open_inode(&self, inode_num: u32) -> Result<Node<D>> {
+        if inode_num > self.superblock().inodes_count() {
+            return Err(Error::InvalidInode {
+                inode: inode_num,
+                reason: "inode number is larger than the inode count",
+            });
+        }
+        if self.is_deleted_orphan(inode_num) {
+            return Err(Error::InvalidInode {
+                inode: inode_num,
+                reason: "inode is an orphan that has been deleted",
+            });
+        }
+        if inode_num < self.superblock().first_inode() && inode_num != Inode::ROOT_INODE {
+            return Err(Error::InvalidInode {
+                inode: inode_num,
+                reason: "inode is reserved for filesystem metadata",
+            });
+        }
+
+        let inode = self.read_inode(inode_num)?;
+        if inode.links_count() == 0 && inode.dtime() != 0 {
+            return Err(Error::InvalidInode {
+                inode: inode_num,
+                reason: "inode has been deleted",
+            });
+        }
+        match inode.mode().file_type() {
+            Some(FileType::RegularFile) => File::new(self, inode, "").map(Node::File),
+            Some(FileType::SymbolicLink) => File::new(self, inode, "").map(Node::Symlink),
+            Some(FileType::Directory) => Directory::new(self, inode, "")
+                .map(|directory| Node::Directory(Box::new(directory))),
+            Some(_) => Ok(Node::Special(inode)),
+            None => Err(Error::InvalidInode {
+                inode: inode_num,
+                reason: "inode is not in use",
+            }),
+        }
+    }
+}
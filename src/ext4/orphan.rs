@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::vec;
+
+use crate::{
+    Error, ParseContext, Result, Volume,
+    ext4::{InodeReader, device::BlockDevice, inode::Inode, superblock::Superblock},
+};
+
+/// Where an orphaned inode was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanSource {
+    /// The `last_orphan` list, linked through each inode's `dtime`
+    List,
+    /// The orphan file inode
+    OrphanFile,
+}
+
+/// What a mount would do with an orphaned inode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanAction {
+    /// The inode has no links left and would be freed
+    Delete,
+    /// The inode is still linked and would be truncated to its size
+    Truncate,
+}
+
+/// How orphaned inodes are presented when reading the volume
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// Show orphaned inodes exactly as they are stored
+    #[default]
+    AsStored,
+    /// Hide orphans a mount would delete from lookups, walks and `open_inode`
+    ///
+    /// Orphans that are still linked are shown as stored. Reads never go past
+    /// the inode size, so a pending truncate does not change their contents.
+    Process,
+}
+
+/// An inode found on the orphan list or in the orphan file
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    inode_number: u32,
+    inode: Inode,
+    source: OrphanSource,
+}
+
+impl Orphan {
+    /// Size of the trailer (magic and checksum) at the end of each orphan file block
+    const BLOCK_TAIL_SIZE: usize = 8;
+    const BLOCK_MAGIC: u32 = 0x0B10_CA04;
+
+    pub fn inode_number(&self) -> u32 {
+        self.inode_number
+    }
+
+    pub fn inode(&self) -> &Inode {
+        &self.inode
+    }
+
+    pub fn source(&self) -> OrphanSource {
+        self.source
+    }
+
+    pub fn size(&self) -> u64 {
+        self.inode.size()
+    }
+
+    pub fn action(&self) -> OrphanAction {
+        if self.inode.links_count() == 0 {
+            OrphanAction::Delete
+        } else {
+            OrphanAction::Truncate
+        }
+    }
+}
+
+/// An orphan scan that leaves reading inodes and the orphan file to the caller
+pub(crate) struct OrphanScan {
+    orphans: Vec<Orphan>,
+    seen: HashSet<u32>,
+    next_listed: Option<u32>,
+    file_entries: vec::IntoIter<u32>,
+    source: OrphanSource,
+}
+
+impl OrphanScan {
+    pub fn new(superblock: &Superblock) -> Self {
+        Self {
+            orphans: Vec::new(),
+            seen: HashSet::new(),
+            next_listed: superblock.last_orphan(),
+            file_entries: Vec::new().into_iter(),
+            source: OrphanSource::List,
+        }
+    }
+
+    /// Load the inode numbers recorded in the contents of the orphan file
+    pub fn load_orphan_file(&mut self, data: &[u8], block_size: u32) -> Result<()> {
+        let mut entries = Vec::new();
+        for (index, block) in data.chunks_exact(block_size as usize).enumerate() {
+            let (slots, tail) = block.split_at(block.len() - Orphan::BLOCK_TAIL_SIZE);
+            let magic = u32::from_le_bytes(tail[..4].try_into().unwrap());
+            if magic != Orphan::BLOCK_MAGIC {
+                return Err(Error::invalid_data(
+                    ParseContext::OrphanFile,
+                    format!("block {} has bad magic {:#x}", index, magic),
+                ));
+            }
+
+            entries.extend(
+                slots
+                    .chunks_exact(4)
+                    .map(|raw| u32::from_le_bytes(raw.try_into().unwrap()))
+                    .filter(|&inode_number| inode_number != 0),
+            );
+        }
+
+        self.file_entries = entries.into_iter();
+        Ok(())
+    }
+
+    /// Get the next inode to read and pass to `found`, `None` once the scan is complete
+    pub fn next_inode(&mut self) -> Result<Option<u32>> {
+        if let Some(inode_number) = self.next_listed.take() {
+            if !self.seen.insert(inode_number) {
+                return Err(Error::invalid_data(
+                    ParseContext::Inode,
+                    format!("orphan list loops back to inode {}", inode_number),
+                ));
+            }
+            return Ok(Some(inode_number));
+        }
+
+        self.source = OrphanSource::OrphanFile;
+        Ok(self
+            .file_entries
+            .find(|&inode_number| self.seen.insert(inode_number)))
+    }
+
+    /// Record the inode returned by the last `next_inode`
+    pub fn found(&mut self, inode_number: u32, inode: Inode) {
+        if self.source == OrphanSource::List {
+            self.next_listed = (inode.dtime() != 0).then_some(inode.dtime());
+        }
+        self.orphans.push(Orphan {
+            inode_number,
+            inode,
+            source: self.source,
+        });
+    }
+
+    pub fn finish(self) -> Vec<Orphan> {
+        self.orphans
+    }
+}
+
+/// Get the inode numbers of the orphans `OrphanPolicy::Process` hides
+pub(crate) fn deleted_orphans(orphans: &[Orphan]) -> HashSet<u32> {
+    orphans
+        .iter()
+        .filter(|orphan| orphan.action() == OrphanAction::Delete)
+        .map(|orphan| orphan.inode_number)
+        .collect()
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Enumerate every orphaned inode on the volume
+    pub fn orphans(&self) -> Result<Vec<Orphan>> {
+        let mut scan = OrphanScan::new(self.superblock());
+
+        if let Some(orphan_file) = self.superblock().orphan_file_inode() {
+            let inode = self.read_inode(orphan_file)?;
+            if inode.size() != 0 {
+                let data = InodeReader::new(self).read_all(&inode)?;
+                scan.load_orphan_file(&data, self.block_size())?;
+            }
+        }
+
+        while let Some(inode_number) = scan.next_inode()? {
+            scan.found(inode_number, self.read_inode(inode_number)?);
+        }
+
+        Ok(scan.finish())
+    }
+
+    /// Choose how orphaned inodes are presented by this volume
+    pub fn with_orphan_policy(mut self, policy: OrphanPolicy) -> Result<Self> {
+        let deleted = match policy {
+            OrphanPolicy::AsStored => HashSet::new(),
+            OrphanPolicy::Process => deleted_orphans(&self.orphans()?),
+        };
+        self.deleted_orphans = Arc::new(deleted);
+        Ok(self)
+    }
+
+    /// Check if an inode is an orphan that has been treated as deleted
+    pub(crate) fn is_deleted_orphan(&self, inode_number: u32) -> bool {
+        self.deleted_orphans.contains(&inode_number)
+    }
+}
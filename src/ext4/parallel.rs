@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use rayon::iter::{ParallelIterator, plumbing::UnindexedConsumer, walk_tree, walk_tree_prefix};
+
+use crate::{
+    Error, Result, Volume, WalkItem,
+    ext4::{
+        DirEntryType, DirectoryEntry, InodeReader, device::BlockDevice, directory::Directory,
+        inode::Inode,
+    },
+};
+
+/// A node of the directory tree while it is being walked
+enum WalkNode {
+    Root(PathBuf, Inode),
+    /// An entry and its inode, once it has been read to check for a directory
+    Entry(PathBuf, DirectoryEntry, Option<Inode>),
+    Failed(Error),
+}
+
+/// A parallel iterator for recursive directory traversal
+///
+/// Directories are split across the rayon pool, so directory data and inodes
+/// of different directories are read concurrently. Items come in no particular
+/// order unless `ordered` is set.
+pub struct ParallelWalker<D: BlockDevice> {
+    volume: Volume<D>,
+    path: PathBuf,
+    inode: Inode,
+    ordered: bool,
+}
+
+impl<D: BlockDevice + Send + Sync> ParallelWalker<D> {
+    pub(crate) fn new(dir: Directory<D>) -> Self {
+        Self {
+            volume: dir.volume.clone(),
+            path: dir.path().to_path_buf(),
+            inode: dir.inode().clone(),
+            ordered: false,
+        }
+    }
+
+    /// Create a walker starting from a specific path
+    pub fn from_path(volume: &Volume<D>, path: impl AsRef<Path>) -> Result<Self> {
+        let directory = volume.open_dir(&path)?;
+        Ok(Self::new(directory))
+    }
+
+    /// Yield items in the same order as `DirectoryWalker`
+    ///
+    /// Each directory is listed before its children, in on-disk entry order.
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Read the inode of an entry that may be a directory, so it is read only once
+    fn entry_node(volume: &Volume<D>, path: PathBuf, entry: DirectoryEntry) -> WalkNode {
+        // Without the filetype feature every entry has to be checked
+        if !matches!(
+            entry.entry_type(),
+            DirEntryType::Dir | DirEntryType::Unknown
+        ) {
+            return WalkNode::Entry(path, entry, None);
+        }
+        match volume.read_inode(entry.inode) {
+            Ok(inode) => WalkNode::Entry(path, entry, Some(inode)),
+            Err(e) => WalkNode::Failed(e),
+        }
+    }
+
+    /// List the children of a node, reading the directory it refers to
+    fn children(volume: &Volume<D>, node: &WalkNode) -> Vec<WalkNode> {
+        let (path, inode) = match node {
+            WalkNode::Root(path, inode) => (path, inode.clone()),
+            WalkNode::Entry(path, _, Some(inode)) if inode.is_directory() => (path, inode.clone()),
+            WalkNode::Entry(..) | WalkNode::Failed(_) => return Vec::new(),
+        };
+
+        let directory = match Directory::new(volume, inode, path) {
+            Ok(directory) => directory,
+            Err(e) => return vec![WalkNode::Failed(e)],
+        };
+
+        directory
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Ok(entry) if entry.is_dot_or_dotdot() => None,
+                Ok(entry) if volume.is_deleted_orphan(entry.inode) => None,
+                Ok(entry) => Some(Self::entry_node(volume, path.join(entry.name()), entry)),
+                Err(e) => Some(WalkNode::Failed(e)),
+            })
+            .collect()
+    }
+
+    /// Turn a node into the item yielded for it, reading its inode and xattrs
+    fn item(volume: &Volume<D>, node: WalkNode) -> Option<Result<WalkItem>> {
+        let (path, entry, inode) = match node {
+            WalkNode::Root(..) => return None,
+            WalkNode::Entry(path, entry, inode) => (path, entry, inode),
+            WalkNode::Failed(e) => return Some(Err(e)),
+        };
+
+        let inode = match inode.map_or_else(|| volume.read_inode(entry.inode), Ok) {
+            Ok(inode) => inode,
+            Err(e) => return Some(Err(e)),
+        };
+        let xattrs = InodeReader::new(volume)
+            .read_xattrs(&inode)
+            .unwrap_or_default();
+
+        Some(Ok(WalkItem::new(
+            path,
+            entry,
+            inode,
+            volume.superblock(),
+            &xattrs,
+        )))
+    }
+}
+
+impl<D: BlockDevice + Send + Sync> ParallelIterator for ParallelWalker<D> {
+    type Item = Result<WalkItem>;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let volume = &self.volume;
+        let root = WalkNode::Root(self.path, self.inode);
+        let children = |node: &WalkNode| Self::children(volume, node);
+        let item = |node| Self::item(volume, node);
+
+        if self.ordered {
+            walk_tree_prefix(root, children)
+                .filter_map(item)
+                .drive_unindexed(consumer)
+        } else {
+            walk_tree(root, children)
+                .filter_map(item)
+                .drive_unindexed(consumer)
+        }
+    }
+}
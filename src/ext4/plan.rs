@@ -0,0 +1,116 @@
+use crate::{
+    Result, Volume,
+    ext4::{InodeReader, device::BlockDevice},
+};
+
+/// A range of file data stored contiguously on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataRun {
+    file: usize,
+    logical_offset: u64,
+    physical_offset: u64,
+    length: u64,
+}
+
+impl DataRun {
+    /// Get the index of the file in the list the plan was made from
+    pub fn file(&self) -> usize {
+        self.file
+    }
+
+    /// Get the offset of this run within the file
+    pub fn logical_offset(&self) -> u64 {
+        self.logical_offset
+    }
+
+    /// Get the byte offset of this run on the device
+    pub fn physical_offset(&self) -> u64 {
+        self.physical_offset
+    }
+
+    /// Get the length of this run in bytes
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+}
+
+/// The data of a set of files, ordered by where it is stored on disk
+///
+/// Executing the plan reads the image roughly sequentially, once, instead of
+/// seeking back and forth between files. Holes and unwritten extents are not
+/// part of the plan, so outputs should be created with their full size first.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractionPlan {
+    files: Vec<u32>,
+    runs: Vec<DataRun>,
+}
+
+impl ExtractionPlan {
+    /// Largest read issued while executing a plan
+    pub const MAX_READ_SIZE: usize = 1024 * 1024;
+
+    /// Get the inode numbers of the planned files, in the order they were given
+    pub fn files(&self) -> &[u32] {
+        &self.files
+    }
+
+    /// Get the data runs sorted by physical offset
+    pub fn runs(&self) -> &[DataRun] {
+        &self.runs
+    }
+
+    /// Get the number of bytes the plan reads
+    pub fn total_bytes(&self) -> u64 {
+        self.runs.iter().map(DataRun::length).sum()
+    }
+
+    /// Stream the planned data in physical order
+    ///
+    /// `write` is called with the index of the file, the offset within that
+    /// file and the data to write there.
+    pub fn execute<D: BlockDevice>(
+        &self,
+        volume: &Volume<D>,
+        mut write: impl FnMut(usize, u64, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let mut buffer = Vec::new();
+        for run in &self.runs {
+            let mut done = 0;
+            while done < run.length {
+                let length = std::cmp::min(run.length - done, Self::MAX_READ_SIZE as u64) as usize;
+                buffer.resize(length, 0);
+                volume
+                    .device()
+                    .read_at(run.physical_offset + done, &mut buffer)?;
+                write(run.file, run.logical_offset + done, &buffer)?;
+                done += length as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Plan reading the data of the given inodes in physical block order
+    pub fn plan_extraction(&self, inodes: impl IntoIterator<Item = u32>) -> Result<ExtractionPlan> {
+        let reader = InodeReader::new(self);
+        let mut plan = ExtractionPlan::default();
+
+        for (file, inode_num) in inodes.into_iter().enumerate() {
+            let inode = self.read_inode(inode_num)?;
+            plan.files.push(inode_num);
+            plan.runs
+                .extend(reader.data_ranges(&inode)?.into_iter().map(
+                    |(logical_offset, physical_offset, length)| DataRun {
+                        file,
+                        logical_offset,
+                        physical_offset,
+                        length,
+                    },
+                ));
+        }
+
+        plan.runs.sort_unstable_by_key(DataRun::physical_offset);
+        Ok(plan)
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use nom::{
+    Finish, IResult,
+    number::complete::{le_u32, le_u64},
+};
+
+use crate::{
+    DirectoryWalker, Error, ParseContext, Result, Volume,
+    ext4::{InodeReader, device::BlockDevice, inode::Inode},
+};
+
+/// The kind of ID a quota file accounts for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuotaType {
+    User,
+    Group,
+    Project,
+}
+
+impl QuotaType {
+    fn magic(&self) -> u32 {
+        match self {
+            QuotaType::User => 0xD9C0_1F11,
+            QuotaType::Group => 0xD9C0_1927,
+            QuotaType::Project => 0xD9C0_3F14,
+        }
+    }
+
+    /// Get the ID of an inode that this quota type charges
+    fn id_of(&self, inode: &Inode) -> u32 {
+        match self {
+            QuotaType::User => inode.uid(),
+            QuotaType::Group => inode.gid(),
+            QuotaType::Project => inode.project_id(),
+        }
+    }
+}
+
+impl std::fmt::Display for QuotaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuotaType::User => write!(f, "user"),
+            QuotaType::Group => write!(f, "group"),
+            QuotaType::Project => write!(f, "project"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct QuotaHeader {
+    magic: u32,
+    version: u32,
+}
+
+impl QuotaHeader {
+    pub const SIZE: usize = 8;
+    pub const VERSION_R1: u32 = 1;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        match Self::parse_le(bytes).finish() {
+            Ok((_, header)) => Ok(header),
+            Err(e) => Err(Error::nom_parse(ParseContext::Quota, e)),
+        }
+    }
+
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, magic) = le_u32(input)?;
+        let (input, version) = le_u32(input)?;
+        Ok((input, Self { magic, version }))
+    }
+}
+
+/// Global information stored after the quota file header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct QuotaInfo {
+    block_grace: u32,
+    inode_grace: u32,
+    flags: u32,
+    blocks: u32,
+    free_block: u32,
+    free_entry: u32,
+}
+
+impl QuotaInfo {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        match Self::parse_le(bytes).finish() {
+            Ok((_, info)) => Ok(info),
+            Err(e) => Err(Error::nom_parse(ParseContext::Quota, e)),
+        }
+    }
+
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, block_grace) = le_u32(input)?;
+        let (input, inode_grace) = le_u32(input)?;
+        let (input, flags) = le_u32(input)?;
+        let (input, blocks) = le_u32(input)?;
+        let (input, free_block) = le_u32(input)?;
+        let (input, free_entry) = le_u32(input)?;
+        Ok((
+            input,
+            Self {
+                block_grace,
+                inode_grace,
+                flags,
+                blocks,
+                free_block,
+                free_entry,
+            },
+        ))
+    }
+
+    /// Grace period in seconds for exceeding the space soft limit
+    pub fn block_grace(&self) -> u32 {
+        self.block_grace
+    }
+
+    /// Grace period in seconds for exceeding the inode soft limit
+    pub fn inode_grace(&self) -> u32 {
+        self.inode_grace
+    }
+
+    /// Number of 1 KiB blocks in the quota file
+    pub fn blocks(&self) -> u32 {
+        self.blocks
+    }
+}
+
+/// A single v2r1 quota record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct QuotaEntry {
+    id: u32,
+    pad: u32,
+    inode_hard_limit: u64,
+    inode_soft_limit: u64,
+    inodes: u64,
+    space_hard_limit: u64,
+    space_soft_limit: u64,
+    space: u64,
+    space_grace_time: u64,
+    inode_grace_time: u64,
+}
+
+impl QuotaEntry {
+    pub const SIZE: usize = 72;
+
+    /// Limits are stored in units of quota blocks
+    const QUOTA_BLOCK_BITS: u32 = 10;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        match Self::parse_le(bytes).finish() {
+            Ok((_, entry)) => Ok(entry),
+            Err(e) => Err(Error::nom_parse(ParseContext::Quota, e)),
+        }
+    }
+
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, id) = le_u32(input)?;
+        let (input, pad) = le_u32(input)?;
+        let (input, inode_hard_limit) = le_u64(input)?;
+        let (input, inode_soft_limit) = le_u64(input)?;
+        let (input, inodes) = le_u64(input)?;
+        let (input, space_hard_limit) = le_u64(input)?;
+        let (input, space_soft_limit) = le_u64(input)?;
+        let (input, space) = le_u64(input)?;
+        let (input, space_grace_time) = le_u64(input)?;
+        let (input, inode_grace_time) = le_u64(input)?;
+        Ok((
+            input,
+            Self {
+                id,
+                pad,
+                inode_hard_limit,
+                inode_soft_limit,
+                inodes,
+                space_hard_limit,
+                space_soft_limit,
+                space,
+                space_grace_time,
+                inode_grace_time,
+            },
+        ))
+    }
+
+    /// Get the user, group or project ID this entry belongs to
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Get the recorded usage
+    pub fn usage(&self) -> QuotaUsage {
+        QuotaUsage {
+            space: self.space,
+            inodes: self.inodes,
+        }
+    }
+
+    /// Get the space hard limit in bytes (0 means unlimited)
+    pub fn space_hard_limit(&self) -> u64 {
+        self.space_hard_limit << Self::QUOTA_BLOCK_BITS
+    }
+
+    /// Get the space soft limit in bytes (0 means unlimited)
+    pub fn space_soft_limit(&self) -> u64 {
+        self.space_soft_limit << Self::QUOTA_BLOCK_BITS
+    }
+
+    /// Get the inode hard limit (0 means unlimited)
+    pub fn inode_hard_limit(&self) -> u64 {
+        self.inode_hard_limit
+    }
+
+    /// Get the inode soft limit (0 means unlimited)
+    pub fn inode_soft_limit(&self) -> u64 {
+        self.inode_soft_limit
+    }
+
+    /// Time (seconds since the epoch) at which the space grace period runs out
+    pub fn space_grace_time(&self) -> u64 {
+        self.space_grace_time
+    }
+
+    /// Time (seconds since the epoch) at which the inode grace period runs out
+    pub fn inode_grace_time(&self) -> u64 {
+        self.inode_grace_time
+    }
+}
+
+/// Space and inode usage charged to an ID
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// Allocated space in bytes
+    pub space: u64,
+    /// Number of inodes
+    pub inodes: u64,
+}
+
+/// A difference between the usage recorded in a quota file and the usage found on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaMismatch {
+    pub id: u32,
+    pub recorded: QuotaUsage,
+    pub computed: QuotaUsage,
+}
+
+/// A parsed quota file (v2 quota tree format)
+#[derive(Debug, Clone)]
+pub struct QuotaFile {
+    quota_type: QuotaType,
+    info: QuotaInfo,
+    entries: BTreeMap<u32, QuotaEntry>,
+}
+
+impl QuotaFile {
+    const BLOCK_SIZE: usize = 1024;
+    const TREE_ROOT: u32 = 1;
+    const TREE_DEPTH: u32 = 4;
+    const DATA_HEADER_SIZE: usize = 16;
+
+    /// Parse the contents of a quota inode
+    pub fn parse(quota_type: QuotaType, data: &[u8]) -> Result<Self> {
+        let header = QuotaHeader::parse(data)?;
+        if header.magic != quota_type.magic() {
+            return Err(Error::invalid_data(
+                ParseContext::Quota,
+                format!(
+                    "bad magic {:#x} for {} quota (expected {:#x})",
+                    header.magic,
+                    quota_type,
+                    quota_type.magic()
+                ),
+            ));
+        }
+        if header.version != QuotaHeader::VERSION_R1 {
+            return Err(Error::invalid_data(
+                ParseContext::Quota,
+                format!("unsupported quota format version {}", header.version),
+            ));
+        }
+
+        let info = QuotaInfo::parse(&data[QuotaHeader::SIZE..])?;
+
+        let mut tree_blocks = BTreeSet::new();
+        let mut data_blocks = BTreeSet::new();
+        Self::collect_data_blocks(
+            data,
+            &mut vec![Self::TREE_ROOT],
+            &mut tree_blocks,
+            &mut data_blocks,
+        )?;
+        if let Some(block_num) = data_blocks.intersection(&tree_blocks).next() {
+            return Err(Error::invalid_data(
+                ParseContext::Quota,
+                format!(
+                    "block {} is used as both a tree and a data block",
+                    block_num
+                ),
+            ));
+        }
+
+        let mut entries = BTreeMap::new();
+        for block_num in data_blocks {
+            let block = Self::block(data, block_num)?;
+            for raw in block[Self::DATA_HEADER_SIZE..].chunks_exact(QuotaEntry::SIZE) {
+                if raw.iter().all(|&b| b == 0) {
+                    continue;
+                }
+                let entry = QuotaEntry::parse(raw)?;
+                entries.insert(entry.id, entry);
+            }
+        }
+
+        Ok(Self {
+            quota_type,
+            info,
+            entries,
+        })
+    }
+
+    fn block(data: &[u8], block_num: u32) -> Result<&[u8]> {
+        let start = block_num as usize * Self::BLOCK_SIZE;
+        data.get(start..start + Self::BLOCK_SIZE).ok_or_else(|| {
+            Error::invalid_data(
+                ParseContext::Quota,
+                format!("tree block {} is outside the quota file", block_num),
+            )
+        })
+    }
+
+    /// Walk the radix tree and gather the data blocks its leaves point to
+    ///
+    /// `path` holds the tree blocks from the root down to the one being read.
+    /// Every tree block may only be referenced once, so a corrupt file cannot
+    /// make the walk loop or fan out over the same blocks.
+    fn collect_data_blocks(
+        data: &[u8],
+        path: &mut Vec<u32>,
+        tree_blocks: &mut BTreeSet<u32>,
+        data_blocks: &mut BTreeSet<u32>,
+    ) -> Result<()> {
+        let block_num = *path.last().expect("path starts at the root");
+        if !tree_blocks.insert(block_num) {
+            return Err(Error::invalid_data(
+                ParseContext::Quota,
+                format!("tree block {} is referenced more than once", block_num),
+            ));
+        }
+        let block = Self::block(data, block_num)?;
+
+        for raw in block.chunks_exact(4) {
+            let child = u32::from_le_bytes(raw.try_into().unwrap());
+            if child == 0 {
+                continue;
+            }
+            if path.contains(&child) {
+                return Err(Error::invalid_data(
+                    ParseContext::Quota,
+                    format!("tree block {} points back to block {}", block_num, child),
+                ));
+            }
+            if path.len() as u32 == Self::TREE_DEPTH {
+                data_blocks.insert(child);
+            } else {
+                path.push(child);
+                Self::collect_data_blocks(data, path, tree_blocks, data_blocks)?;
+                path.pop();
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn quota_type(&self) -> QuotaType {
+        self.quota_type
+    }
+
+    pub fn info(&self) -> &QuotaInfo {
+        &self.info
+    }
+
+    /// Get the entry for a specific ID
+    pub fn entry(&self, id: u32) -> Option<&QuotaEntry> {
+        self.entries.get(&id)
+    }
+
+    /// Iterate over all entries, ordered by ID
+    pub fn entries(&self) -> impl Iterator<Item = &QuotaEntry> {
+        self.entries.values()
+    }
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Get the inode number of the quota file for the given type, if any
+    pub fn quota_inode(&self, quota_type: QuotaType) -> Option<u32> {
+        match quota_type {
+            QuotaType::User => self.superblock().usr_quota_inode(),
+            QuotaType::Group => self.superblock().grp_quota_inode(),
+            QuotaType::Project => self.superblock().prj_quota_inode(),
+        }
+    }
+
+    /// Read and parse the quota file for the given type
+    pub fn read_quota(&self, quota_type: QuotaType) -> Result<Option<QuotaFile>> {
+        let Some(inode_num) = self.quota_inode(quota_type) else {
+            return Ok(None);
+        };
+
+        let inode = self.read_inode(inode_num)?;
+        let data = InodeReader::new(self).read_all(&inode)?;
+
+        QuotaFile::parse(quota_type, &data).map(Some)
+    }
+
+    /// Compute per-ID usage by walking the directory tree from the root
+    ///
+    /// Hard links are only counted once. Orphans the walk does not reach, such
+    /// as unlinked files that were still open, are charged too, as the kernel
+    /// keeps charging them until they are freed.
+    pub fn quota_usage(&self, quota_type: QuotaType) -> Result<BTreeMap<u32, QuotaUsage>> {
+        let mut usage: BTreeMap<u32, QuotaUsage> = BTreeMap::new();
+        let mut charge = |inode: &Inode| {
+            let entry = usage.entry(quota_type.id_of(inode)).or_default();
+            entry.space += inode.allocated_bytes(self.superblock());
+            entry.inodes += 1;
+        };
+
+        charge(&self.read_inode(Inode::ROOT_INODE)?);
+
+        let mut seen = HashSet::new();
+        for item in DirectoryWalker::from_path(self, "/")? {
+            let item = item?;
+            if seen.insert(item.ino()) {
+                charge(item.inode());
+            }
+        }
+
+        for orphan in self.orphans()? {
+            if seen.insert(orphan.inode_number()) {
+                charge(orphan.inode());
+            }
+        }
+
+        Ok(usage)
+    }
+
+    /// Compare the usage recorded in a quota file with the usage found on disk
+    ///
+    /// Returns `None` if the volume has no quota file of this type.
+    pub fn check_quota(&self, quota_type: QuotaType) -> Result<Option<Vec<QuotaMismatch>>> {
+        let Some(quota) = self.read_quota(quota_type)? else {
+            return Ok(None);
+        };
+        let computed = self.quota_usage(quota_type)?;
+
+        let ids: BTreeSet<u32> = quota
+            .entries
+            .keys()
+            .chain(computed.keys())
+            .copied()
+            .collect();
+
+        let mismatches = ids
+            .into_iter()
+            .filter_map(|id| {
+                let recorded = quota.entry(id).map(|e| e.usage()).unwrap_or_default();
+                let computed = computed.get(&id).copied().unwrap_or_default();
+                (recorded != computed).then_some(QuotaMismatch {
+                    id,
+                    recorded,
+                    computed,
+                })
+            })
+            .collect();
+
+        Ok(Some(mismatches))
+    }
+}
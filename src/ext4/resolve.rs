@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{
+    Error, Result, Volume,
+    ext4::{InodeReader, device::BlockDevice, inode::Inode},
+};
+
+/// How symbolic links inside the image are handled while resolving a path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Never follow symlinks, a symlink in the middle of a path is an error
+    NoFollow,
+    /// Follow symlinks in every component except the last one (like `lstat`)
+    #[default]
+    FollowIntermediate,
+    /// Follow symlinks in every component, including the last one (like `stat`)
+    FollowAll,
+}
+
+impl SymlinkPolicy {
+    pub(crate) fn follows(&self, is_last: bool) -> bool {
+        match self {
+            SymlinkPolicy::NoFollow => false,
+            SymlinkPolicy::FollowIntermediate => !is_last,
+            SymlinkPolicy::FollowAll => true,
+        }
+    }
+}
+
+/// Queue the components of a path for resolution, returning whether it is absolute
+pub(crate) fn push_components(pending: &mut VecDeque<OsString>, path: &Path) -> bool {
+    let components: Vec<OsString> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect();
+
+    for component in components.into_iter().rev() {
+        pending.push_front(component);
+    }
+    path.has_root()
+}
+
+/// Maximum number of symlinks followed while resolving a single path
+pub(crate) const MAX_SYMLINK_HOPS: u32 = 40;
+
+/// A directory lookup the resolver needs before it can continue
+pub(crate) struct Lookup<'a> {
+    pub parent_num: u32,
+    pub parent: &'a Inode,
+    pub parent_path: &'a Path,
+    pub name: &'a [u8],
+}
+
+/// Path resolution that leaves directory lookups and inode reads to the caller
+///
+/// Each `Lookup` is answered with `found`, `Volume` goes through its dentry
+/// cache while `AsyncVolume` scans the directory.
+pub(crate) struct PathResolver<'p> {
+    path: &'p Path,
+    policy: SymlinkPolicy,
+    pending: VecDeque<OsString>,
+    component: OsString,
+    // Inodes from the root down to the current component, so `..` can step back up
+    ancestors: Vec<(u32, Inode)>,
+    current_path: PathBuf,
+    link: Option<Inode>,
+    hops: u32,
+}
+
+impl<'p> PathResolver<'p> {
+    pub fn new(path: &'p Path, policy: SymlinkPolicy, root: Inode) -> Self {
+        let mut pending = VecDeque::new();
+        push_components(&mut pending, path);
+        Self {
+            path,
+            policy,
+            pending,
+            component: OsString::new(),
+            ancestors: vec![(Inode::ROOT_INODE, root)],
+            current_path: PathBuf::from("/"),
+            link: None,
+            hops: 0,
+        }
+    }
+
+    /// Get the next lookup to perform, `None` once the whole path is resolved
+    pub fn next_lookup(&mut self) -> Result<Option<Lookup<'_>>> {
+        loop {
+            let Some(component) = self.pending.pop_front() else {
+                return Ok(None);
+            };
+            if component != ".." {
+                self.component = component;
+                break;
+            }
+
+            // Like the kernel, `..` at the root stays at the root
+            if self.ancestors.len() > 1 {
+                self.ancestors.pop();
+                self.current_path.pop();
+            }
+        }
+
+        let (parent_num, parent) = self.ancestors.last().expect("root is never popped");
+        if !parent.is_directory() {
+            let path = format!("{}", self.current_path.display());
+            return Err(Error::NotADirectory(path));
+        }
+
+        Ok(Some(Lookup {
+            parent_num: *parent_num,
+            parent,
+            parent_path: &self.current_path,
+            name: self.component.as_encoded_bytes(),
+        }))
+    }
+
+    /// Record the result of the last lookup
+    ///
+    /// Returns the inode when it is a symlink to follow, its target must then
+    /// be passed to `follow`.
+    pub fn found(&mut self, child: Option<(u32, Inode)>) -> Result<Option<&Inode>> {
+        let Some((inode_num, inode)) = child else {
+            return Err(self.not_found());
+        };
+
+        if inode.is_symlink() && self.policy.follows(self.pending.is_empty()) {
+            self.hops += 1;
+            if self.hops > MAX_SYMLINK_HOPS {
+                return Err(Error::SymlinkLoop(format!("{}", self.path.display())));
+            }
+            return Ok(Some(self.link.insert(inode)));
+        }
+
+        self.ancestors.push((inode_num, inode));
+        self.current_path.push(&self.component);
+        Ok(None)
+    }
+
+    /// Continue through the target of the symlink returned by `found`
+    pub fn follow(&mut self, target: &Path) -> Result<()> {
+        self.link = None;
+        if target.as_os_str().is_empty() {
+            return Err(self.not_found());
+        }
+        if push_components(&mut self.pending, target) {
+            self.ancestors.truncate(1);
+            self.current_path = PathBuf::from("/");
+        }
+        Ok(())
+    }
+
+    /// Get the inode the path resolved to and the path it resolved to
+    pub fn finish(mut self) -> (Inode, PathBuf) {
+        let (_, inode) = self.ancestors.pop().expect("root is never popped");
+        (inode, self.current_path)
+    }
+
+    fn not_found(&self) -> Error {
+        Error::PathNotFound {
+            path: format!("{}", self.path.display()),
+            component: self.component.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Maximum number of symlinks followed while resolving a single path
+    pub const MAX_SYMLINK_HOPS: u32 = MAX_SYMLINK_HOPS;
+
+    /// Resolve a path to its inode and the path it resolved to, with no `..` or followed symlinks
+    pub(crate) fn resolve_path(
+        &self,
+        path: &Path,
+        policy: SymlinkPolicy,
+    ) -> Result<(Inode, PathBuf)> {
+        let mut resolver = PathResolver::new(path, policy, self.read_inode(Inode::ROOT_INODE)?);
+
+        while let Some(lookup) = resolver.next_lookup()? {
+            let child = self
+                .lookup_child(
+                    lookup.parent_num,
+                    lookup.parent,
+                    lookup.parent_path,
+                    lookup.name,
+                )?
+                .filter(|&inode_num| !self.is_deleted_orphan(inode_num))
+                .map(|inode_num| self.read_inode(inode_num).map(|inode| (inode_num, inode)))
+                .transpose()?;
+
+            if let Some(link) = resolver.found(child)? {
+                let target = InodeReader::new(self).symlink_target(link)?;
+                resolver.follow(&target)?;
+            }
+        }
+
+        Ok(resolver.finish())
+    }
+
+    /// Read the target of a symlink without following it
+    pub fn read_link(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let (inode, _) = self.resolve_path(path, SymlinkPolicy::FollowIntermediate)?;
+        if !inode.is_symlink() {
+            return Err(Error::NotASymlink(format!("{}", path.display())));
+        }
+        InodeReader::new(self).symlink_target(&inode)
+    }
+
+    /// Get the absolute path with every symlink inside the image resolved
+    pub fn canonicalize(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        self.resolve_path(path.as_ref(), SymlinkPolicy::FollowAll)
+            .map(|(_, path)| path)
+    }
+}
@@ -1,12 +1,15 @@
-use crate::ext4::block::BlockGroupDescriptor;
+use crate::ext4::{block::BlockGroupDescriptor, byte_array, le_u32_array};
 use crate::{Error, ParseContext, Result};
 use bitflags::bitflags;
-use nom::Finish;
-use nom_derive::{NomLE, Parse};
-use std::cmp::Ordering;
+use core::cmp::Ordering;
+use nom::{
+    Finish, IResult,
+    combinator::verify,
+    number::complete::{le_u8, le_u16, le_u32, le_u64},
+};
 
 #[repr(C)]
-#[derive(Debug, Clone, PartialEq, Eq, NomLE)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Superblock {
     inodes_count: u32,
     blocks_count_lo: u32,
@@ -24,7 +27,6 @@ pub struct Superblock {
     mount_count: u16,
     max_mount_count: u16,
 
-    #[nom(Verify(*magic == 0xEF53))]
     magic: u16,
 
     state: State,
@@ -40,13 +42,10 @@ pub struct Superblock {
     inode_size: u16,
     block_group_index: u16,
 
-    #[nom(Parse = "CompatibleFeatures::parse")]
     features_compatible: CompatibleFeatures,
 
-    #[nom(Parse = "IncompatibleFeatures::parse")]
     features_incompatible: IncompatibleFeatures,
 
-    #[nom(Parse = "ReadOnlyCompatibleFeatures::parse")]
     features_read_only: ReadOnlyCompatibleFeatures,
 
     uuid: [u8; 16],
@@ -65,7 +64,6 @@ pub struct Superblock {
     journal_backup_type: u8,
     desc_size: u16,
 
-    #[nom(Parse = "DefaultMountOptions::parse")]
     default_mount_opts: DefaultMountOptions,
 
     first_meta_bg: u32,
@@ -76,7 +74,6 @@ pub struct Superblock {
     free_blocks_count_hi: u32,
     min_extra_isize: u16,
     want_extra_isize: u16,
-    #[nom(Parse = "Flags::parse")]
     flags: Flags,
     raid_stride: u16,
     mmp_interval: u16,
@@ -109,7 +106,20 @@ pub struct Superblock {
     encrypt_algos: [EncryptionAlgorithm; 4],
     encrypt_pw_salt: [u8; 16],
     lpf_ino: u32,
-    padding: [u32; 100],
+    prj_quota_inum: u32,
+    checksum_seed: u32,
+    write_time_hi: u8,
+    mount_time_hi: u8,
+    mkfs_time_hi: u8,
+    last_check_time_hi: u8,
+    first_error_time_hi: u8,
+    last_error_time_hi: u8,
+    first_error_errcode: u8,
+    last_error_errcode: u8,
+    encoding: u16,
+    encoding_flags: u16,
+    orphan_file_inum: u32,
+    padding: [u32; 94],
     checksum: u32,
 }
 
@@ -119,12 +129,224 @@ impl Superblock {
     pub const EXT4_SUPERBLOCK_OS_HURD: u32 = 1;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, superblock)) => Ok(superblock),
             Err(e) => Err(Error::nom_parse(ParseContext::Superblock, e)),
         }
     }
 
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, inodes_count) = le_u32(input)?;
+        let (input, blocks_count_lo) = le_u32(input)?;
+        let (input, reserved_blocks_count_lo) = le_u32(input)?;
+        let (input, free_blocks_count_lo) = le_u32(input)?;
+        let (input, free_inodes_count) = le_u32(input)?;
+        let (input, first_data_block) = le_u32(input)?;
+        let (input, log_block_size) = le_u32(input)?;
+        let (input, log_cluster_size) = le_u32(input)?;
+        let (input, blocks_per_group) = le_u32(input)?;
+        let (input, frags_per_group) = le_u32(input)?;
+        let (input, inodes_per_group) = le_u32(input)?;
+        let (input, mount_time) = le_u32(input)?;
+        let (input, write_time) = le_u32(input)?;
+        let (input, mount_count) = le_u16(input)?;
+        let (input, max_mount_count) = le_u16(input)?;
+        let (input, magic) = verify(le_u16, |magic| *magic == 0xEF53)(input)?;
+        let (input, state) = State::parse(input)?;
+        let (input, errors) = ErrorPolicy::parse(input)?;
+        let (input, minor_rev_level) = le_u16(input)?;
+        let (input, last_check_time) = le_u32(input)?;
+        let (input, check_interval) = le_u32(input)?;
+        let (input, creator_os) = CreatorOS::parse(input)?;
+        let (input, rev_level) = Revision::parse(input)?;
+        let (input, def_resuid) = le_u16(input)?;
+        let (input, def_resgid) = le_u16(input)?;
+        let (input, first_inode) = le_u32(input)?;
+        let (input, inode_size) = le_u16(input)?;
+        let (input, block_group_index) = le_u16(input)?;
+        let (input, features_compatible) = CompatibleFeatures::parse(input)?;
+        let (input, features_incompatible) = IncompatibleFeatures::parse(input)?;
+        let (input, features_read_only) = ReadOnlyCompatibleFeatures::parse(input)?;
+        let (input, uuid) = byte_array(input)?;
+        let (input, volume_name) = byte_array(input)?;
+        let (input, last_mounted) = byte_array(input)?;
+        let (input, algorithm_usage_bitmap) = le_u32(input)?;
+        let (input, s_prealloc_blocks) = le_u8(input)?;
+        let (input, s_prealloc_dir_blocks) = le_u8(input)?;
+        let (input, s_reserved_gdt_blocks) = le_u16(input)?;
+        let (input, journal_uuid) = byte_array(input)?;
+        let (input, journal_inode_number) = le_u32(input)?;
+        let (input, journal_dev) = le_u32(input)?;
+        let (input, last_orphan) = le_u32(input)?;
+        let (input, hash_seed) = le_u32_array(input)?;
+        let (input, default_hash_version) = DefaultHashVersion::parse(input)?;
+        let (input, journal_backup_type) = le_u8(input)?;
+        let (input, desc_size) = le_u16(input)?;
+        let (input, default_mount_opts) = DefaultMountOptions::parse(input)?;
+        let (input, first_meta_bg) = le_u32(input)?;
+        let (input, mkfs_time) = le_u32(input)?;
+        let (input, journal_blocks) = le_u32_array(input)?;
+        let (input, blocks_count_hi) = le_u32(input)?;
+        let (input, reserved_blocks_count_hi) = le_u32(input)?;
+        let (input, free_blocks_count_hi) = le_u32(input)?;
+        let (input, min_extra_isize) = le_u16(input)?;
+        let (input, want_extra_isize) = le_u16(input)?;
+        let (input, flags) = Flags::parse(input)?;
+        let (input, raid_stride) = le_u16(input)?;
+        let (input, mmp_interval) = le_u16(input)?;
+        let (input, mmp_block) = le_u64(input)?;
+        let (input, raid_stripe_width) = le_u32(input)?;
+        let (input, log_groups_per_flex) = le_u8(input)?;
+        let (input, checksum_type) = le_u8(input)?;
+        let (input, reserved_pad) = le_u16(input)?;
+        let (input, kbytes_written) = le_u64(input)?;
+        let (input, snapshot_inum) = le_u32(input)?;
+        let (input, snapshot_id) = le_u32(input)?;
+        let (input, snapshot_r_blocks_count) = le_u64(input)?;
+        let (input, snapshot_list) = le_u32(input)?;
+        let (input, error_count) = le_u32(input)?;
+        let (input, first_error_time) = le_u32(input)?;
+        let (input, first_error_ino) = le_u32(input)?;
+        let (input, first_error_block) = le_u64(input)?;
+        let (input, first_error_func) = byte_array(input)?;
+        let (input, first_error_line) = le_u32(input)?;
+        let (input, last_error_time) = le_u32(input)?;
+        let (input, last_error_ino) = le_u32(input)?;
+        let (input, last_error_line) = le_u32(input)?;
+        let (input, last_error_block) = le_u64(input)?;
+        let (input, last_error_func) = byte_array(input)?;
+        let (input, mount_opts) = byte_array(input)?;
+        let (input, usr_quota_inum) = le_u32(input)?;
+        let (input, grp_quota_inum) = le_u32(input)?;
+        let (input, overhead_clusters) = le_u32(input)?;
+        let (input, backup_bgs) = le_u32_array(input)?;
+        let (input, encrypt_algos) = EncryptionAlgorithm::parse_array(input)?;
+        let (input, encrypt_pw_salt) = byte_array(input)?;
+        let (input, lpf_ino) = le_u32(input)?;
+        let (input, prj_quota_inum) = le_u32(input)?;
+        let (input, checksum_seed) = le_u32(input)?;
+        let (input, write_time_hi) = le_u8(input)?;
+        let (input, mount_time_hi) = le_u8(input)?;
+        let (input, mkfs_time_hi) = le_u8(input)?;
+        let (input, last_check_time_hi) = le_u8(input)?;
+        let (input, first_error_time_hi) = le_u8(input)?;
+        let (input, last_error_time_hi) = le_u8(input)?;
+        let (input, first_error_errcode) = le_u8(input)?;
+        let (input, last_error_errcode) = le_u8(input)?;
+        let (input, encoding) = le_u16(input)?;
+        let (input, encoding_flags) = le_u16(input)?;
+        let (input, orphan_file_inum) = le_u32(input)?;
+        let (input, padding) = le_u32_array(input)?;
+        let (input, checksum) = le_u32(input)?;
+        Ok((
+            input,
+            Self {
+                inodes_count,
+                blocks_count_lo,
+                reserved_blocks_count_lo,
+                free_blocks_count_lo,
+                free_inodes_count,
+                first_data_block,
+                log_block_size,
+                log_cluster_size,
+                blocks_per_group,
+                frags_per_group,
+                inodes_per_group,
+                mount_time,
+                write_time,
+                mount_count,
+                max_mount_count,
+                magic,
+                state,
+                errors,
+                minor_rev_level,
+                last_check_time,
+                check_interval,
+                creator_os,
+                rev_level,
+                def_resuid,
+                def_resgid,
+                first_inode,
+                inode_size,
+                block_group_index,
+                features_compatible,
+                features_incompatible,
+                features_read_only,
+                uuid,
+                volume_name,
+                last_mounted,
+                algorithm_usage_bitmap,
+                s_prealloc_blocks,
+                s_prealloc_dir_blocks,
+                s_reserved_gdt_blocks,
+                journal_uuid,
+                journal_inode_number,
+                journal_dev,
+                last_orphan,
+                hash_seed,
+                default_hash_version,
+                journal_backup_type,
+                desc_size,
+                default_mount_opts,
+                first_meta_bg,
+                mkfs_time,
+                journal_blocks,
+                blocks_count_hi,
+                reserved_blocks_count_hi,
+                free_blocks_count_hi,
+                min_extra_isize,
+                want_extra_isize,
+                flags,
+                raid_stride,
+                mmp_interval,
+                mmp_block,
+                raid_stripe_width,
+                log_groups_per_flex,
+                checksum_type,
+                reserved_pad,
+                kbytes_written,
+                snapshot_inum,
+                snapshot_id,
+                snapshot_r_blocks_count,
+                snapshot_list,
+                error_count,
+                first_error_time,
+                first_error_ino,
+                first_error_block,
+                first_error_func,
+                first_error_line,
+                last_error_time,
+                last_error_ino,
+                last_error_line,
+                last_error_block,
+                last_error_func,
+                mount_opts,
+                usr_quota_inum,
+                grp_quota_inum,
+                overhead_clusters,
+                backup_bgs,
+                encrypt_algos,
+                encrypt_pw_salt,
+                lpf_ino,
+                prj_quota_inum,
+                checksum_seed,
+                write_time_hi,
+                mount_time_hi,
+                mkfs_time_hi,
+                last_check_time_hi,
+                first_error_time_hi,
+                last_error_time_hi,
+                first_error_errcode,
+                last_error_errcode,
+                encoding,
+                encoding_flags,
+                orphan_file_inum,
+                padding,
+                checksum,
+            },
+        ))
+    }
+
     pub fn block_size(&self) -> u32 {
         1024 << self.log_block_size
     }
@@ -140,7 +362,15 @@ impl Superblock {
         ((self.blocks_count_hi as u64) << 32) | self.blocks_count_lo as u64
     }
 
+    /// Get the size of a group descriptor, which is only larger than 32 bytes on 64-bit filesystems
     pub fn descriptor_size(&self) -> u16 {
+        if !self
+            .features_incompatible()
+            .contains(IncompatibleFeatures::Bit64)
+        {
+            return BlockGroupDescriptor::MIN_SIZE;
+        }
+
         let size = self.desc_size;
         match size.cmp(&BlockGroupDescriptor::MIN_SIZE) {
             Ordering::Less => BlockGroupDescriptor::MIN_SIZE,
@@ -148,6 +378,37 @@ impl Superblock {
         }
     }
 
+    /// Get the byte offset of a block group descriptor
+    pub fn descriptor_offset(&self, bg_index: u32) -> Result<u64> {
+        let block_group_count = self.block_group_count();
+        if bg_index >= block_group_count {
+            return Err(Error::InvalidBlockGroup {
+                index: bg_index,
+                count: block_group_count,
+            });
+        }
+
+        // The descriptor table starts in the block after the superblock
+        let block_size = self.block_size() as u64;
+        let first_block = if block_size == Self::SUPERBLOCK_OFFSET {
+            2
+        } else {
+            1
+        };
+        Ok(first_block * block_size + bg_index as u64 * self.descriptor_size() as u64)
+    }
+
+    /// Get the block group of an inode and its byte offset within that group's inode table
+    pub fn inode_location(&self, inode_num: u32) -> Result<(u32, u64)> {
+        if inode_num == 0 {
+            return Err(Error::inode_zero());
+        }
+
+        let bg_index = (inode_num - 1) / self.inodes_per_group;
+        let inode_index = (inode_num - 1) % self.inodes_per_group;
+        Ok((bg_index, inode_index as u64 * self.inode_size()))
+    }
+
     pub fn inodes_in_group_cnt(&self, bgid: u32) -> u32 {
         let block_group_count = self.block_group_count();
         let inodes_per_group = self.inodes_per_group;
@@ -171,6 +432,93 @@ impl Superblock {
         self.inode_size as u64
     }
 
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Get the behaviour when errors are detected
+    pub fn errors(&self) -> ErrorPolicy {
+        self.errors
+    }
+
+    pub fn creator_os(&self) -> CreatorOS {
+        self.creator_os
+    }
+
+    pub fn revision(&self) -> Revision {
+        self.rev_level
+    }
+
+    pub fn default_hash_version(&self) -> DefaultHashVersion {
+        self.default_hash_version
+    }
+
+    pub fn encryption_algorithms(&self) -> [EncryptionAlgorithm; 4] {
+        self.encrypt_algos
+    }
+
+    /// Get the first non-reserved inode number
+    pub fn first_inode(&self) -> u32 {
+        self.first_inode
+    }
+
+    pub fn inodes_count(&self) -> u32 {
+        self.inodes_count
+    }
+
+    pub fn features_compatible(&self) -> CompatibleFeatures {
+        self.features_compatible
+    }
+
+    pub fn features_incompatible(&self) -> IncompatibleFeatures {
+        self.features_incompatible
+    }
+
+    pub fn features_read_only(&self) -> ReadOnlyCompatibleFeatures {
+        self.features_read_only
+    }
+
+    /// Get the user quota inode number, if one is set
+    pub fn usr_quota_inode(&self) -> Option<u32> {
+        (self.usr_quota_inum != 0).then_some(self.usr_quota_inum)
+    }
+
+    /// Get the group quota inode number, if one is set
+    pub fn grp_quota_inode(&self) -> Option<u32> {
+        (self.grp_quota_inum != 0).then_some(self.grp_quota_inum)
+    }
+
+    /// Get the project quota inode number, if project quotas are enabled
+    pub fn prj_quota_inode(&self) -> Option<u32> {
+        let enabled = self
+            .features_read_only
+            .contains(ReadOnlyCompatibleFeatures::ProjectQuota);
+        (enabled && self.prj_quota_inum != 0).then_some(self.prj_quota_inum)
+    }
+
+    /// Get the block number of the MMP block
+    pub fn mmp_block(&self) -> u64 {
+        self.mmp_block
+    }
+
+    /// Get the MMP update interval in seconds
+    pub fn mmp_interval(&self) -> u16 {
+        self.mmp_interval
+    }
+
+    /// Get the head of the orphan inode list, if it is not empty
+    pub fn last_orphan(&self) -> Option<u32> {
+        (self.last_orphan != 0).then_some(self.last_orphan)
+    }
+
+    /// Get the orphan file inode number, if the orphan file feature is enabled
+    pub fn orphan_file_inode(&self) -> Option<u32> {
+        let enabled = self
+            .features_compatible
+            .contains(CompatibleFeatures::OrphanFile);
+        (enabled && self.orphan_file_inum != 0).then_some(self.orphan_file_inum)
+    }
+
     pub fn volume_name(&self) -> &str {
         // Find the first null byte or use the full length
         let end = self
@@ -179,41 +527,49 @@ impl Superblock {
             .position(|&b| b == 0)
             .unwrap_or(self.volume_name.len());
         // Safety: volume names in ext4 are ASCII, but use lossy conversion for safety
-        std::str::from_utf8(&self.volume_name[..end]).unwrap_or("")
+        core::str::from_utf8(&self.volume_name[..end]).unwrap_or("")
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, NomLE)]
-#[repr(u16)]
-pub enum State {
-    Clean = 0x0001,
-    Errors = 0x0002,
-    Orphan = 0x0004,
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct State: u16 {
+        const Clean = 0x0001;
+        const Errors = 0x0002;
+        const Orphan = 0x0004;
+    }
+}
+
+impl State {
+    pub fn parse(input: &[u8]) -> nom::IResult<&[u8], Self> {
+        let (input, bits) = nom::number::complete::le_u16(input)?;
+        Ok((input, Self::from_bits_retain(bits)))
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, NomLE)]
-#[repr(u16)]
-pub enum ErrorPolicy {
-    Continue = 1,
-    ReadOnly = 2,
-    Panic = 3,
+open_enum! {
+    pub enum ErrorPolicy: u16 => le_u16 {
+        Continue = 1,
+        ReadOnly = 2,
+        Panic = 3,
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, NomLE)]
-#[repr(u32)]
-pub enum Revision {
-    Original = 0,
-    Dynamic = 1,
+open_enum! {
+    pub enum Revision: u32 => le_u32 {
+        Original = 0,
+        Dynamic = 1,
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, NomLE)]
-#[repr(u32)]
-pub enum CreatorOS {
-    Linux = 0,
-    Hurd = 1,
-    Masix = 2,
-    FreeBSD = 3,
-    Lites = 4,
+open_enum! {
+    pub enum CreatorOS: u32 => le_u32 {
+        Linux = 0,
+        Hurd = 1,
+        Masix = 2,
+        FreeBSD = 3,
+        Lites = 4,
+    }
 }
 
 bitflags! {
@@ -259,13 +615,15 @@ bitflags! {
         const LargeDirectory = 0x4000;
         const InlineData = 0x8000;
         const EncryptedInodes = 0x10000;
+        const Casefold = 0x20000;
     }
 }
 
 impl IncompatibleFeatures {
     pub fn parse(input: &[u8]) -> nom::IResult<&[u8], Self> {
         let (input, bits) = nom::number::complete::le_u32(input)?;
-        Ok((input, Self::from_bits_truncate(bits)))
+        // Keep unknown bits so the compatibility check can report them
+        Ok((input, Self::from_bits_retain(bits)))
     }
 }
 
@@ -294,19 +652,21 @@ bitflags! {
 impl ReadOnlyCompatibleFeatures {
     pub fn parse(input: &[u8]) -> nom::IResult<&[u8], Self> {
         let (input, bits) = nom::number::complete::le_u32(input)?;
-        Ok((input, Self::from_bits_truncate(bits)))
+        // Keep unknown bits so the compatibility check can report them
+        Ok((input, Self::from_bits_retain(bits)))
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, NomLE)]
-#[repr(u8)]
-pub enum DefaultHashVersion {
-    Legacy = 0,
-    HalfMD4 = 1,
-    Tea = 2,
-    LegacyUnsigned = 3,
-    HalfMD4Unsigned = 4,
-    TeaUnsigned = 5,
+open_enum! {
+    pub enum DefaultHashVersion: u8 => le_u8 {
+        Legacy = 0,
+        HalfMD4 = 1,
+        Tea = 2,
+        LegacyUnsigned = 3,
+        HalfMD4Unsigned = 4,
+        TeaUnsigned = 5,
+        SipHash = 6,
+    }
 }
 
 bitflags! {
@@ -350,11 +710,23 @@ impl Flags {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, NomLE)]
-#[repr(u8)]
-pub enum EncryptionAlgorithm {
-    Invalid = 0,
-    Aes256Xts = 1,
-    Aes256Gcm = 2,
-    Aes256Cbc = 3,
+open_enum! {
+    pub enum EncryptionAlgorithm: u8 => le_u8 {
+        Invalid = 0,
+        Aes256Xts = 1,
+        Aes256Gcm = 2,
+        Aes256Cbc = 3,
+        Aes256Cts = 4,
+        Aes128Cbc = 5,
+        Aes128Cts = 6,
+        Adiantum = 9,
+        Aes256Hctr2 = 10,
+    }
+}
+
+impl EncryptionAlgorithm {
+    pub fn parse_array(input: &[u8]) -> nom::IResult<&[u8], [Self; 4]> {
+        let (input, algorithms) = nom::multi::count(Self::parse, 4)(input)?;
+        Ok((input, algorithms.try_into().unwrap()))
+    }
 }
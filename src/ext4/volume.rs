@@ -1,57 +1,97 @@
-use std::{
-    io::{Read, Seek, SeekFrom},
-    path::{Path, PathBuf},
-    sync::Arc,
-};
+use std::{borrow::Cow, collections::HashSet, path::Path, sync::Arc};
 
 use crate::{
     Directory, Error, File, Result,
-    ext4::{block::BlockGroupDescriptor, inode::Inode, superblock::Superblock},
-    utils::NormalizePath,
+    ext4::{
+        block::{BlockGroupDescriptor, GroupTable},
+        cache::BlockCache,
+        compat::{CompatibilityReport, FeaturePolicy},
+        dentry::DentryCache,
+        device::BlockDevice,
+        inode::Inode,
+        resolve::SymlinkPolicy,
+        superblock::Superblock,
+    },
 };
 
 /// Represents an ext4 filesystem volume
 #[derive(Debug)]
-pub struct Volume<R: Read + Seek, F: Fn() -> R> {
-    reader_factory: Arc<F>,
+pub struct Volume<D: BlockDevice> {
+    device: Arc<D>,
     superblock: Superblock,
     block_size: u32,
+    compatibility: Arc<CompatibilityReport>,
+    groups: GroupTable,
+    pub(crate) deleted_orphans: Arc<HashSet<u32>>,
+    pub(crate) block_cache: Option<Arc<BlockCache>>,
+    pub(crate) dentry_cache: Option<Arc<DentryCache>>,
 }
 
-impl<R: Read + Seek, F: Fn() -> R> Clone for Volume<R, F> {
+impl<D: BlockDevice> Clone for Volume<D> {
     fn clone(&self) -> Self {
         Self {
-            reader_factory: Arc::clone(&self.reader_factory),
+            device: Arc::clone(&self.device),
             superblock: self.superblock.clone(),
             block_size: self.block_size,
+            compatibility: Arc::clone(&self.compatibility),
+            groups: self.groups.clone(),
+            deleted_orphans: Arc::clone(&self.deleted_orphans),
+            block_cache: self.block_cache.clone(),
+            dentry_cache: self.dentry_cache.clone(),
         }
     }
 }
 
-impl<R: Read + Seek, F: Fn() -> R> Volume<R, F> {
+impl<D: BlockDevice> Volume<D> {
     pub const MIN_BLOCK_SIZE: u32 = 1024;
 
-    /// Create a new Volume from a reader factory
-    pub fn new(reader_factory: F) -> Result<Self> {
-        let mut reader = reader_factory();
-        reader.seek(SeekFrom::Start(Superblock::SUPERBLOCK_OFFSET))?;
-
-        let mut sb_buf = vec![0u8; Superblock::SIZE];
-        reader.read_exact(&mut sb_buf)?;
+    /// Create a new Volume from a block device
+    ///
+    /// Unsupported features are only recorded in `compatibility()`, use `open`
+    /// with `FeaturePolicy::Strict` to refuse such images instead.
+    pub fn new(device: D) -> Result<Self> {
+        Self::open(device, FeaturePolicy::Permissive)
+    }
 
+    /// Create a new Volume from a block device, checking features against a policy
+    pub fn open(device: D, policy: FeaturePolicy) -> Result<Self> {
+        let sb_buf = device.read_bytes(Superblock::SUPERBLOCK_OFFSET, Superblock::SIZE)?;
         let superblock = Superblock::parse(&sb_buf)?;
         let block_size = superblock.block_size();
 
+        let compatibility = CompatibilityReport::new(&superblock);
+        if policy == FeaturePolicy::Strict && !compatibility.is_safe() {
+            return Err(Error::UnsupportedFeatures(compatibility));
+        }
+
+        let (offset, length) = GroupTable::location(&superblock)?;
+        let groups = GroupTable::parse(&superblock, &device.read_bytes(offset, length)?)?;
+
         Ok(Self {
-            reader_factory: Arc::new(reader_factory),
+            device: Arc::new(device),
             superblock,
             block_size,
+            compatibility: Arc::new(compatibility),
+            groups,
+            deleted_orphans: Arc::new(HashSet::new()),
+            block_cache: None,
+            dentry_cache: None,
         })
     }
 
-    /// Create a new reader from the factory
-    pub fn reader(&self) -> R {
-        (self.reader_factory)()
+    /// Get the underlying block device
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Get a shared handle to the underlying block device
+    pub(crate) fn shared_device(&self) -> Arc<D> {
+        Arc::clone(&self.device)
+    }
+
+    /// Read bytes from the device at an absolute offset, borrowing them when possible
+    pub(crate) fn read_bytes(&self, offset: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        self.device.read_bytes(offset, len)
     }
 
     /// Get the volume name
@@ -69,121 +109,72 @@ impl<R: Read + Seek, F: Fn() -> R> Volume<R, F> {
         &self.superblock
     }
 
+    /// Get the features of this volume the reader does not fully support
+    pub fn compatibility(&self) -> &CompatibilityReport {
+        &self.compatibility
+    }
+
     /// Get the block size
     pub fn block_size(&self) -> u32 {
         self.block_size
     }
 
-    /// Read a block group descriptor
+    /// Get a block group descriptor from the table loaded when the volume was opened
     pub fn read_block_group_descriptor(&self, bg_index: u32) -> Result<BlockGroupDescriptor> {
-        let block_group_count = self.superblock.block_group_count();
-        if bg_index >= block_group_count {
-            return Err(Error::InvalidBlockGroup {
-                index: bg_index,
-                count: block_group_count,
-            });
-        }
-
-        let desc_size = self.superblock.descriptor_size() as u64;
-        let first_block = if self.block_size == Self::MIN_BLOCK_SIZE {
-            2
-        } else {
-            1
-        };
-        let offset = first_block * self.block_size as u64 + bg_index as u64 * desc_size;
-
-        let mut reader = self.reader();
-        reader.seek(SeekFrom::Start(offset))?;
-
-        let mut buffer = vec![0u8; desc_size as usize];
-        reader.read_exact(&mut buffer)?;
+        self.groups.get(bg_index)
+    }
 
-        BlockGroupDescriptor::parse(&buffer)
+    /// Iterate over the descriptors of all block groups, in group order
+    pub fn block_groups(&self) -> std::slice::Iter<'_, BlockGroupDescriptor> {
+        self.groups.iter()
     }
 
     /// Read an inode from the filesystem
     pub fn read_inode(&self, inode_num: u32) -> Result<Inode> {
-        if inode_num == 0 {
-            return Err(Error::inode_zero());
-        }
-
-        let inodes_per_group = self.superblock.inodes_per_group();
-        let inode_size = self.superblock.inode_size();
-
-        let bg_index = (inode_num - 1) / inodes_per_group;
-        let inode_index = (inode_num - 1) % inodes_per_group;
-
-        let inode_table_block = self
-            .read_block_group_descriptor(bg_index)
-            .map(|bg_desc| bg_desc.inode_table_first_block())?;
-
-        let offset = inode_table_block * self.block_size as u64 + inode_index as u64 * inode_size;
-
-        let mut reader = self.reader();
-        reader.seek(SeekFrom::Start(offset))?;
-
-        let mut buffer = vec![0u8; inode_size as usize];
-        reader.read_exact(&mut buffer)?;
-
+        let offset = self.groups.inode_offset(&self.superblock, inode_num)?;
+        let buffer = self.read_metadata(offset, self.superblock.inode_size() as usize)?;
         Inode::parse(&buffer)
     }
 
-    /// Lookup a path and return its inode along with the normalized path
-    fn lookup_path_with_normalized(&self, path: impl AsRef<Path>) -> Result<(Inode, PathBuf)> {
-        let original_path = path.as_ref();
-        let normalized_path = original_path.normalize()?;
-
-        // Get components iterator
-        let mut components = normalized_path
-            .components()
-            .filter(|c| matches!(c, std::path::Component::Normal(_)))
-            .peekable();
-
-        // If no components left, return root inode
-        if components.peek().is_none() {
-            return Ok((self.read_inode(Inode::ROOT_INODE)?, PathBuf::from("/")));
-        }
-
-        let mut current_inode = self.read_inode(Inode::ROOT_INODE)?;
-        let mut current_path = PathBuf::from("/");
-
-        for component in components {
-            let directory = Directory::new(self, current_inode, &current_path)?;
-            let component_str = component
-                .as_os_str()
-                .to_str()
-                .ok_or(Error::InvalidUtf8InPath)?;
-
-            current_inode = match directory.find(component_str) {
-                Some(entry) => self.read_inode(entry.inode)?,
-                None => {
-                    return Err(Error::PathNotFound {
-                        path: format!("{}", original_path.display()),
-                        component: component_str.to_string(),
-                    });
-                }
-            };
-            current_path.push(component_str);
-        }
-
-        Ok((current_inode, current_path))
-    }
-
     /// Lookup a path and return its inode
+    ///
+    /// Symlinks are followed in every component but the last one.
     pub fn lookup_path(&self, path: impl AsRef<Path>) -> Result<Inode> {
-        self.lookup_path_with_normalized(path)
+        self.lookup_path_with(path, SymlinkPolicy::FollowIntermediate)
+    }
+
+    /// Lookup a path with the given symlink policy and return its inode
+    pub fn lookup_path_with(&self, path: impl AsRef<Path>, policy: SymlinkPolicy) -> Result<Inode> {
+        self.resolve_path(path.as_ref(), policy)
             .map(|(inode, _)| inode)
     }
 
     /// Open a file for reading
-    pub fn open_file(&self, path: impl AsRef<Path>) -> Result<File<R>> {
-        let (inode, normalized_path) = self.lookup_path_with_normalized(&path)?;
-        File::new(self, inode, normalized_path)
+    ///
+    /// Symlinks are followed in every component but the last one, so opening a
+    /// symlink gives access to its target string.
+    pub fn open_file(&self, path: impl AsRef<Path>) -> Result<File<D>> {
+        self.open_file_with(path, SymlinkPolicy::FollowIntermediate)
+    }
+
+    /// Open a file for reading with the given symlink policy
+    pub fn open_file_with(&self, path: impl AsRef<Path>, policy: SymlinkPolicy) -> Result<File<D>> {
+        let (inode, resolved_path) = self.resolve_path(path.as_ref(), policy)?;
+        File::new(self, inode, resolved_path)
+    }
+
+    /// Open a directory for listing, following symlinks in every component
+    pub fn open_dir(&self, path: impl AsRef<Path>) -> Result<Directory<D>> {
+        self.open_dir_with(path, SymlinkPolicy::FollowAll)
     }
 
-    /// Open a directory for listing
-    pub fn open_dir(&self, path: impl AsRef<Path>) -> Result<Directory<R, F>> {
-        let (inode, normalized_path) = self.lookup_path_with_normalized(&path)?;
-        Directory::new(self, inode, normalized_path)
+    /// Open a directory for listing with the given symlink policy
+    pub fn open_dir_with(
+        &self,
+        path: impl AsRef<Path>,
+        policy: SymlinkPolicy,
+    ) -> Result<Directory<D>> {
+        let (inode, resolved_path) = self.resolve_path(path.as_ref(), policy)?;
+        Directory::new(self, inode, resolved_path)
     }
 }
@@ -1,18 +1,25 @@
-use std::{
-    io::{Read, Seek},
-    path::{Path, PathBuf},
-};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 
 use crate::ext4::{
     DirectoryEntry, Result, Volume,
-    directory::Directory,
+    device::BlockDevice,
+    directory::{Directory, Entries},
     inode::{FileType, Inode, Mode},
     inode_reader::InodeReader,
+    metadata::Metadata,
+    superblock::Superblock,
+    xattr::XAttrEntry,
 };
 
 /// A walker for recursive directory traversal
-pub struct DirectoryWalker<R: Read + Seek, F: Fn() -> R> {
-    stack: Vec<Directory<R, F>>,
+///
+/// Directories are streamed block by block, so memory use grows with the depth
+/// of the tree rather than the size of its directories.
+pub struct DirectoryWalker<D: BlockDevice> {
+    volume: Volume<D>,
+    reader: InodeReader<D>,
+    stack: Vec<(PathBuf, Entries<D>)>,
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +32,16 @@ pub struct EntryAttributes {
 }
 
 impl EntryAttributes {
+    pub(crate) fn new(inode: &Inode, xattrs: &[XAttrEntry]) -> Self {
+        Self {
+            mode: inode.mode(),
+            uid: inode.uid(),
+            gid: inode.gid(),
+            selinux: xattrs.iter().find_map(|x| x.selinux_context()),
+            capabilities: xattrs.iter().find_map(|x| x.capability_string()),
+        }
+    }
+
     /// Get the mode of the entry
     pub fn mode(&self) -> Mode {
         self.mode
@@ -68,18 +85,49 @@ pub struct WalkItem {
     path: PathBuf,
     entry: DirectoryEntry,
     inode: Inode,
+    metadata: Metadata,
     attributes: EntryAttributes,
 }
 
 impl WalkItem {
+    pub(crate) fn new(
+        path: PathBuf,
+        entry: DirectoryEntry,
+        inode: Inode,
+        superblock: &Superblock,
+        xattrs: &[XAttrEntry],
+    ) -> Self {
+        Self {
+            path,
+            entry,
+            metadata: Metadata::new(&inode, superblock),
+            attributes: EntryAttributes::new(&inode, xattrs),
+            inode,
+        }
+    }
+
     pub fn inode(&self) -> &Inode {
         &self.inode
     }
 
+    /// Get the inode number of this entry
+    pub fn ino(&self) -> u32 {
+        self.entry.inode
+    }
+
+    /// Get the number of hard links to the inode
+    pub fn links_count(&self) -> u16 {
+        self.inode.links_count()
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
 
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
     pub fn attributes(&self) -> &EntryAttributes {
         &self.attributes
     }
@@ -91,80 +139,86 @@ impl WalkItem {
             .expect("Invalid file type in inode")
     }
 
-    /// Get the name of this entry
-    pub fn name(&self) -> &str {
-        self.entry.name_str()
+    /// Get the name of this entry, lossless on Unix
+    pub fn name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    /// Get the raw bytes of the name as stored on disk
+    pub fn name_bytes(&self) -> &[u8] {
+        self.entry.name_bytes()
     }
 }
 
-impl<R: Read + Seek, F: Fn() -> R> DirectoryWalker<R, F> {
-    pub(crate) fn new(dir: Directory<R, F>) -> Self {
-        Self { stack: vec![dir] }
+impl<D: BlockDevice> DirectoryWalker<D> {
+    pub(crate) fn new(dir: Directory<D>) -> Self {
+        let volume = dir.volume.clone();
+        let path = dir.path().to_path_buf();
+        Self {
+            reader: InodeReader::new(&volume),
+            volume,
+            stack: vec![(path, dir.into_iter())],
+        }
     }
 
     /// Create a walker starting from a specific path
-    pub fn from_path(volume: &Volume<R, F>, path: impl AsRef<Path>) -> Result<Self> {
+    pub fn from_path(volume: &Volume<D>, path: impl AsRef<Path>) -> Result<Self> {
         let directory = volume.open_dir(&path)?;
         Ok(Self::new(directory))
     }
 
     pub fn current_path(&self) -> Option<&Path> {
-        self.stack.last().map(|frame| frame.path())
+        self.stack.last().map(|(path, _)| path.as_path())
     }
 }
 
-impl<R: Read + Seek, F: Fn() -> R> Iterator for DirectoryWalker<R, F> {
+impl<D: BlockDevice> Iterator for DirectoryWalker<D> {
     type Item = Result<WalkItem>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(current) = self.stack.last_mut() {
-            let entry = match current.entries_mut().pop() {
-                Some(e) => e,
+        while let Some((path, entries)) = self.stack.last_mut() {
+            let entry = match entries.next() {
+                Some(Ok(e)) => e,
+                Some(Err(e)) => return Some(Err(e)),
                 None => {
                     self.stack.pop();
                     continue;
                 }
             };
 
-            let entry_name = entry.name_str();
-            if entry_name == "." || entry_name == ".." {
+            if entry.is_dot_or_dotdot() {
                 continue;
             }
 
-            let item_path = current.path().join(entry_name);
+            if self.volume.is_deleted_orphan(entry.inode) {
+                continue;
+            }
+
+            let item_path = path.join(entry.name());
 
-            let inode = match current.volume.read_inode(entry.inode) {
+            let inode = match self.volume.read_inode(entry.inode) {
                 Ok(inode) => inode,
                 Err(e) => return Some(Err(e)),
             };
 
-            let xattrs = InodeReader::new(&current.volume)
-                .read_xattrs(&inode)
-                .unwrap_or_default();
-
-            let attributes = EntryAttributes {
-                mode: inode.mode(),
-                uid: inode.uid(),
-                gid: inode.gid(),
-                selinux: xattrs.iter().find_map(|x| x.selinux_context()),
-                capabilities: xattrs.iter().find_map(|x| x.capability_string()),
-            };
+            let xattrs = self.reader.read_xattrs(&inode).unwrap_or_default();
 
             if inode.is_directory() {
-                match Directory::new(&current.volume, inode.clone(), &item_path) {
+                match Directory::new(&self.volume, inode.clone(), &item_path) {
                     Err(e) => return Some(Err(e)),
                     Ok(directory) => {
-                        self.stack.push(directory);
+                        self.stack.push((item_path.clone(), directory.into_iter()));
                     }
                 }
             }
 
-            return Some(Ok(WalkItem {
-                path: item_path,
+            return Some(Ok(WalkItem::new(
+                item_path,
                 entry,
                 inode,
-                attributes,
-            }));
+                self.volume.superblock(),
+                &xattrs,
+            )));
         }
 
         None
@@ -1,12 +1,23 @@
-use nom::Finish;
-use nom_derive::{NomLE, Parse};
-
-use crate::{Error, ParseContext, Result};
-
-#[derive(Debug, Clone, Copy, NomLE)]
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use nom::{
+    Finish, IResult,
+    combinator::verify,
+    number::complete::{le_u8, le_u16, le_u32},
+};
+
+use crate::{
+    Error, ParseContext, Result,
+    ext4::{byte_array, superblock::EncryptionAlgorithm},
+};
+
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 struct XAttrHeader {
-    #[nom(Verify(*magic == 0xEA020000))]
     magic: u32,
     refcount: u32,
     blocks: u32,
@@ -19,17 +30,36 @@ impl XAttrHeader {
     pub const SIZE: usize = 32;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => Ok(descriptor),
             Err(e) => Err(Error::nom_parse(ParseContext::XAttrHeader, e)),
         }
     }
+
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, magic) = verify(le_u32, |magic| *magic == 0xEA020000)(input)?;
+        let (input, refcount) = le_u32(input)?;
+        let (input, blocks) = le_u32(input)?;
+        let (input, hash) = le_u32(input)?;
+        let (input, checksum) = le_u32(input)?;
+        let (input, _reserved) = byte_array(input)?;
+        Ok((
+            input,
+            Self {
+                magic,
+                refcount,
+                blocks,
+                hash,
+                checksum,
+                _reserved,
+            },
+        ))
+    }
 }
 
-#[derive(Debug, Clone, Copy, NomLE)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub(crate) struct XAttrIbodyHeader {
-    #[nom(Verify(*magic == 0xEA020000))]
     magic: u32,
 }
 
@@ -37,14 +67,19 @@ impl XAttrIbodyHeader {
     pub const SIZE: usize = 4;
 
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => Ok(descriptor),
             Err(e) => Err(Error::nom_parse(ParseContext::XAttrIbodyHeader, e)),
         }
     }
+
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, magic) = verify(le_u32, |magic| *magic == 0xEA020000)(input)?;
+        Ok((input, Self { magic }))
+    }
 }
 
-#[derive(Debug, Clone, NomLE)]
+#[derive(Debug, Clone)]
 #[repr(C)]
 struct XAttrEntryHeader {
     name_len: u8,
@@ -57,36 +92,60 @@ struct XAttrEntryHeader {
 
 impl XAttrEntryHeader {
     pub fn parse(bytes: &[u8]) -> Result<Self> {
-        match Parse::parse(bytes).finish() {
+        match Self::parse_le(bytes).finish() {
             Ok((_, descriptor)) => Ok(descriptor),
             Err(e) => Err(Error::nom_parse(ParseContext::XAttrEntry, e)),
         }
     }
 
+    fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, name_len) = le_u8(input)?;
+        let (input, name_index) = XAttrNameIndex::parse(input)?;
+        let (input, value_offs) = le_u16(input)?;
+        let (input, value_inum) = le_u32(input)?;
+        let (input, value_size) = le_u32(input)?;
+        let (input, hash) = le_u32(input)?;
+        Ok((
+            input,
+            Self {
+                name_len,
+                name_index,
+                value_offs,
+                value_inum,
+                value_size,
+                hash,
+            },
+        ))
+    }
+
     pub fn is_end_of_entries(&self) -> bool {
         // TODO: According to docs self.value_inum is also set zero to mark end of entries but we need to handle the case when it's not zero so let's avoid checking it for now
-        (self.name_len as u32 | self.name_index as u32 | self.value_offs as u32) == 0
+        (self.name_len as u32 | self.name_index.raw() as u32 | self.value_offs as u32) == 0
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct XAttrEntry {
     header: XAttrEntryHeader,
-    name: String,
+    name: Vec<u8>,
     value: Option<Vec<u8>>,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, NomLE)]
-#[repr(u8)]
-pub enum XAttrNameIndex {
-    NoPrefix = 0,
-    User = 1,
-    SystemPosixAclAccess = 2,
-    SystemPosixAclDefault = 3,
-    Trusted = 4,
-    Security = 6,
-    System = 7,
-    SystemRichAcl = 8,
+open_enum! {
+    pub enum XAttrNameIndex: u8 => le_u8 {
+        NoPrefix = 0,
+        User = 1,
+        SystemPosixAclAccess = 2,
+        SystemPosixAclDefault = 3,
+        Trusted = 4,
+        Lustre = 5,
+        Security = 6,
+        System = 7,
+        SystemRichAcl = 8,
+        /// fscrypt context, not visible to userspace
+        Encryption = 9,
+        Hurd = 10,
+    }
 }
 
 impl XAttrNameIndex {
@@ -100,17 +159,22 @@ impl XAttrNameIndex {
     }
 }
 
-impl std::fmt::Display for XAttrNameIndex {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for XAttrNameIndex {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let s = match self {
             XAttrNameIndex::NoPrefix => "",
             XAttrNameIndex::User => "user.",
             XAttrNameIndex::SystemPosixAclAccess => "system.posix_acl_access",
             XAttrNameIndex::SystemPosixAclDefault => "system.posix_acl_default",
             XAttrNameIndex::Trusted => "trusted.",
+            XAttrNameIndex::Lustre => "lustre.",
             XAttrNameIndex::Security => "security.",
             XAttrNameIndex::System => "system.",
             XAttrNameIndex::SystemRichAcl => "system.richacl",
+            XAttrNameIndex::Encryption => "encryption.",
+            XAttrNameIndex::Hurd => "gnu.",
+            // Unknown indexes have no prefix, so show the raw number instead
+            XAttrNameIndex::Unknown(index) => return write!(f, "index{}.", index),
         };
         write!(f, "{}", s)
     }
@@ -143,12 +207,11 @@ impl XAttrEntry {
             let available = entry_data.len().saturating_sub(Self::HEADER_SIZE);
             let name = entry_data
                 .get(Self::HEADER_SIZE..name_end)
-                .map(String::from_utf8_lossy)
                 .ok_or(Error::XAttrNameOutOfBounds {
                     name_len: header.name_len,
                     available,
                 })?
-                .to_string();
+                .to_vec();
 
             let mut value = None;
             if header.value_inum == 0 && header.value_size > 0 {
@@ -172,18 +235,63 @@ impl XAttrEntry {
 
     /// Get the full size of this entry (header + name, aligned to 4 bytes)
     pub fn size(&self) -> usize {
-        let size = Self::HEADER_SIZE + self.name.len();
+        let size = Self::HEADER_SIZE + self.header.name_len as usize;
         // Align to 4 bytes
         (size + 3) & !3
     }
 
+    /// Get the name index that selects the attribute prefix
+    pub fn name_index(&self) -> XAttrNameIndex {
+        self.header.name_index
+    }
+
+    /// Get the raw attribute name without the prefix
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Get the raw attribute value, if it is stored inline
+    pub fn value(&self) -> Option<&[u8]> {
+        self.value.as_deref()
+    }
+
     /// Get the full attribute name including the prefix
     pub fn full_name(&self) -> String {
         if self.header.name_index.is_acl() {
             format!("{}", self.header.name_index)
         } else {
-            format!("{}{}", self.header.name_index, self.name)
+            format!(
+                "{}{}",
+                self.header.name_index,
+                String::from_utf8_lossy(&self.name)
+            )
+        }
+    }
+
+    /// Check if this is the `system.data` attribute holding the tail of inline data
+    pub fn is_inline_data(&self) -> bool {
+        self.header.name_index == XAttrNameIndex::System && self.name == b"data"
+    }
+
+    /// Get the inline data stored in a `system.data` attribute
+    pub fn inline_data(&self) -> Option<&[u8]> {
+        if !self.is_inline_data() {
+            return None;
+        }
+        Some(self.value.as_deref().unwrap_or_default())
+    }
+
+    /// Check if this is the fscrypt encryption context
+    pub fn is_encryption_context(&self) -> bool {
+        self.header.name_index == XAttrNameIndex::Encryption && self.name == b"c"
+    }
+
+    /// Decode the fscrypt encryption context
+    pub fn encryption_context(&self) -> Option<EncryptionContext> {
+        if !self.is_encryption_context() {
+            return None;
         }
+        EncryptionContext::parse(self.value.as_deref()?).ok()
     }
 
     /// Check if this is a SELinux context attribute
@@ -202,11 +310,8 @@ impl XAttrEntry {
             return None;
         }
 
-        let trimmed = self
-            .value
-            .as_deref()
-            .map(|v| v.strip_suffix(&[0]))
-            .unwrap_or(self.value.as_deref())?;
+        let value = self.value.as_deref()?;
+        let trimmed = value.strip_suffix(&[0]).unwrap_or(value);
 
         Some(String::from_utf8_lossy(trimmed).to_string())
     }
@@ -214,13 +319,27 @@ impl XAttrEntry {
     /// Parse capability value and return it as a hex string
     /// Format: capabilities=0x... or empty if no caps
     pub fn capability_string(&self) -> Option<String> {
-        #[derive(Debug, NomLE)]
+        #[derive(Debug)]
         struct CapData {
             permitted: u32,
             _inheritable: u32,
         }
 
-        #[derive(Debug, NomLE)]
+        impl CapData {
+            fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+                let (input, permitted) = le_u32(input)?;
+                let (input, _inheritable) = le_u32(input)?;
+                Ok((
+                    input,
+                    Self {
+                        permitted,
+                        _inheritable,
+                    },
+                ))
+            }
+        }
+
+        #[derive(Debug)]
         struct VfsCapData {
             _magic_etc: u32,
             data: [CapData; 2], // VFS_CAP_U32
@@ -228,12 +347,25 @@ impl XAttrEntry {
 
         impl VfsCapData {
             pub fn parse(bytes: &[u8]) -> Result<Self> {
-                match Parse::parse(bytes).finish() {
+                match Self::parse_le(bytes).finish() {
                     Ok((_, descriptor)) => Ok(descriptor),
                     Err(e) => Err(Error::nom_parse(ParseContext::Capability, e)),
                 }
             }
 
+            fn parse_le(input: &[u8]) -> IResult<&[u8], Self> {
+                let (input, _magic_etc) = le_u32(input)?;
+                let (input, low) = CapData::parse_le(input)?;
+                let (input, high) = CapData::parse_le(input)?;
+                Ok((
+                    input,
+                    Self {
+                        _magic_etc,
+                        data: [low, high],
+                    },
+                ))
+            }
+
             pub fn capabilities(&self) -> Option<u64> {
                 let permitted_lo = self.data.first()?.permitted;
                 let permitted_hi = self.data.get(1)?.permitted;
@@ -255,6 +387,91 @@ impl XAttrEntry {
     }
 }
 
+/// The fscrypt policy stored in an encrypted inode's `c` attribute (name index 9)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionContext {
+    version: u8,
+    contents_mode: EncryptionAlgorithm,
+    filenames_mode: EncryptionAlgorithm,
+    flags: u8,
+    master_key: Vec<u8>,
+    nonce: [u8; 16],
+}
+
+impl EncryptionContext {
+    const V1: u8 = 1;
+    const V2: u8 = 2;
+    const V1_KEY_SIZE: usize = 8;
+    const V2_KEY_SIZE: usize = 16;
+    const NONCE_SIZE: usize = 16;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let (key_offset, key_size) = match bytes.first() {
+            Some(&Self::V1) => (4, Self::V1_KEY_SIZE),
+            // v2 has a data unit size byte and 3 reserved bytes before the key
+            Some(&Self::V2) => (8, Self::V2_KEY_SIZE),
+            Some(version) => {
+                return Err(Error::invalid_data(
+                    ParseContext::XAttrEntry,
+                    format!("unknown encryption context version {}", version),
+                ));
+            }
+            None => {
+                return Err(Error::invalid_data(
+                    ParseContext::XAttrEntry,
+                    "empty encryption context",
+                ));
+            }
+        };
+
+        let nonce_offset = key_offset + key_size;
+        let nonce = bytes
+            .get(nonce_offset..nonce_offset + Self::NONCE_SIZE)
+            .ok_or_else(|| {
+                Error::invalid_data(
+                    ParseContext::XAttrEntry,
+                    format!("encryption context too short ({} bytes)", bytes.len()),
+                )
+            })?;
+
+        Ok(Self {
+            version: bytes[0],
+            contents_mode: EncryptionAlgorithm::from(bytes[1]),
+            filenames_mode: EncryptionAlgorithm::from(bytes[2]),
+            flags: bytes[3],
+            master_key: bytes[key_offset..nonce_offset].to_vec(),
+            nonce: nonce.try_into().unwrap(),
+        })
+    }
+
+    /// Get the policy version (1 or 2)
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn contents_mode(&self) -> EncryptionAlgorithm {
+        self.contents_mode
+    }
+
+    pub fn filenames_mode(&self) -> EncryptionAlgorithm {
+        self.filenames_mode
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Get the master key descriptor (v1) or identifier (v2)
+    pub fn master_key(&self) -> &[u8] {
+        &self.master_key
+    }
+
+    pub fn nonce(&self) -> &[u8; 16] {
+        &self.nonce
+    }
+}
+
+/// Parse the xattrs stored in an external xattr block
 pub fn parse_xattrs_from_block(block_data: &[u8]) -> Result<Vec<XAttrEntry>> {
     XAttrHeader::parse(block_data)?; // Validate magic
 
@@ -1,7 +1,12 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod ext4;
 pub mod utils;
 
-pub use ext4::{
-    Directory, DirectoryWalker, EntryAttributes, Error, File, FileType, ParseContext, Result,
-    Volume, WalkItem,
-};
+#[cfg(feature = "parallel")]
+pub use ext4::ParallelWalker;
+#[cfg(feature = "std")]
+pub use ext4::{Directory, DirectoryWalker, EntryAttributes, File, Volume, WalkItem};
+pub use ext4::{Error, FileType, Metadata, ParseContext, Result, Timestamp};
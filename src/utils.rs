@@ -1,6 +1,13 @@
-use std::path::{Component, Path, PathBuf};
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
 
-use thiserror::Error;
+use crate::ext4::CompatibilityReport;
 
 /// The kind of structure being parsed when a nom error occurred
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -15,10 +22,13 @@ pub enum ParseContext {
     XAttrIbodyHeader,
     XAttrEntry,
     Capability,
+    Quota,
+    OrphanFile,
+    Mmp,
 }
 
-impl std::fmt::Display for ParseContext {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for ParseContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ParseContext::Superblock => write!(f, "superblock"),
             ParseContext::BlockGroupDescriptor => write!(f, "block group descriptor"),
@@ -30,69 +40,151 @@ impl std::fmt::Display for ParseContext {
             ParseContext::XAttrIbodyHeader => write!(f, "xattr ibody header"),
             ParseContext::XAttrEntry => write!(f, "xattr entry"),
             ParseContext::Capability => write!(f, "capability"),
+            ParseContext::Quota => write!(f, "quota file"),
+            ParseContext::OrphanFile => write!(f, "orphan file"),
+            ParseContext::Mmp => write!(f, "MMP block"),
         }
     }
 }
 
-#[derive(Error, Debug)]
+#[derive(Debug)]
 pub enum Error {
     /// IO error during read/write operations
-    #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
 
     /// Nom parsing error with context about what was being parsed
-    #[error("Failed to parse {context}: {kind:?}")]
     NomParse {
         context: ParseContext,
         kind: nom::error::ErrorKind,
     },
 
     /// Data validation error during parsing (e.g., buffer too small, invalid offsets)
-    #[error("Invalid {context} data: {message}")]
     InvalidData {
         context: ParseContext,
         message: String,
     },
 
     /// Invalid inode number (e.g., inode 0 which is reserved)
-    #[error("Invalid inode number {inode}: {reason}")]
     InvalidInode { inode: u32, reason: &'static str },
 
     /// Block group index out of range
-    #[error("Block group {index} is out of range (filesystem has {count} block groups)")]
     InvalidBlockGroup { index: u32, count: u32 },
 
     /// File or directory not found during path lookup
-    #[error("Path not found: '{path}' (component '{component}' does not exist)")]
     PathNotFound { path: String, component: String },
 
     /// Invalid UTF-8 in path component
-    #[error("Invalid UTF-8 in path component")]
     InvalidUtf8InPath,
 
     /// Expected a directory but found something else
-    #[error("Not a directory: '{0}'")]
     NotADirectory(String),
 
     /// Expected a regular file or symlink
-    #[error("Not a regular file or symlink: '{0}'")]
     NotAFile(String),
 
+    /// Expected a symbolic link
+    NotASymlink(String),
+
+    /// Too many symbolic links were followed while resolving a path
+    SymlinkLoop(String),
+
     /// Path normalization failed (e.g., too many parent directory references)
-    #[error("Invalid path '{path}': {reason}")]
     InvalidPath { path: String, reason: &'static str },
 
     /// Attempted to read beyond the end of file
-    #[error("Read beyond end of file (file size: {file_size}, requested offset: {offset})")]
     ReadBeyondEof { file_size: u64, offset: u64 },
 
     /// Corrupted directory entry data
-    #[error("Corrupted directory entry at offset {0}")]
     CorruptedDirectoryEntry(usize),
 
+    /// The image uses features that cannot be read correctly
+    UnsupportedFeatures(CompatibilityReport),
+
     /// XAttr name is out of bounds
-    #[error("XAttr entry name out of bounds (name_len: {name_len}, available: {available})")]
     XAttrNameOutOfBounds { name_len: u8, available: usize },
+
+    /// Read outside the bounds of an in-memory device
+    OutOfBounds { offset: u64, len: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::NomParse { context, kind } => {
+                write!(f, "Failed to parse {}: {:?}", context, kind)
+            }
+            Error::InvalidData { context, message } => {
+                write!(f, "Invalid {} data: {}", context, message)
+            }
+            Error::InvalidInode { inode, reason } => {
+                write!(f, "Invalid inode number {}: {}", inode, reason)
+            }
+            Error::InvalidBlockGroup { index, count } => write!(
+                f,
+                "Block group {} is out of range (filesystem has {} block groups)",
+                index, count
+            ),
+            Error::PathNotFound { path, component } => write!(
+                f,
+                "Path not found: '{}' (component '{}' does not exist)",
+                path, component
+            ),
+            Error::InvalidUtf8InPath => write!(f, "Invalid UTF-8 in path component"),
+            Error::NotADirectory(path) => write!(f, "Not a directory: '{}'", path),
+            Error::NotAFile(path) => write!(f, "Not a regular file or symlink: '{}'", path),
+            Error::NotASymlink(path) => write!(f, "Not a symbolic link: '{}'", path),
+            Error::SymlinkLoop(path) => {
+                write!(f, "Too many levels of symbolic links: '{}'", path)
+            }
+            Error::InvalidPath { path, reason } => {
+                write!(f, "Invalid path '{}': {}", path, reason)
+            }
+            Error::ReadBeyondEof { file_size, offset } => write!(
+                f,
+                "Read beyond end of file (file size: {}, requested offset: {})",
+                file_size, offset
+            ),
+            Error::CorruptedDirectoryEntry(offset) => {
+                write!(f, "Corrupted directory entry at offset {}", offset)
+            }
+            Error::UnsupportedFeatures(report) => {
+                write!(f, "Unsupported filesystem features: {}", report)
+            }
+            Error::XAttrNameOutOfBounds {
+                name_len,
+                available,
+            } => write!(
+                f,
+                "XAttr entry name out of bounds (name_len: {}, available: {})",
+                name_len, available
+            ),
+            Error::OutOfBounds { offset, len } => write!(
+                f,
+                "Read of {} bytes at offset {} is out of bounds",
+                len, offset
+            ),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
 }
 
 impl Error {
@@ -121,12 +213,34 @@ impl Error {
     }
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Convert raw on-disk name bytes to an `OsStr`
+///
+/// This is lossless on Unix. Elsewhere, names that are not valid UTF-8 are
+/// converted lossily.
+#[cfg(feature = "std")]
+pub fn os_str_from_bytes(bytes: &[u8]) -> Cow<'_, OsStr> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        match String::from_utf8_lossy(bytes) {
+            Cow::Borrowed(name) => Cow::Borrowed(OsStr::new(name)),
+            Cow::Owned(name) => Cow::Owned(name.into()),
+        }
+    }
+}
 
+#[cfg(feature = "std")]
 pub trait NormalizePath {
     fn normalize(&self) -> Result<PathBuf>;
 }
 
+#[cfg(feature = "std")]
 impl NormalizePath for Path {
     // Taken from: https://github.com/rust-lang/rust/pull/134696
     fn normalize(&self) -> Result<PathBuf> {